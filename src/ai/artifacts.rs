@@ -0,0 +1,148 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Stripping chat-template residue (`<|assistant|>`, `[INST]`, ...) that some
+//! models leak into their text output.
+//!
+//! On by default (`STRIP_ARTIFACTS`); `STRIP_ARTIFACTS_EXTRA` adds
+//! comma-separated markers of its own. The untouched text stays available
+//! through `__debug`.
+
+use worker::Env;
+use crate::config;
+use serde_json::Value;
+
+/// Template tokens that never belong in output, removed wherever they appear.
+/// Longer markers come before their prefixes.
+const ANYWHERE: &[&str] = &[
+    "<|start_header_id|>assistant<|end_header_id|>",
+    "<|start_header_id|>",
+    "<|end_header_id|>",
+    "<|begin_of_text|>",
+    "<|end_of_text|>",
+    "<|eot_id|>",
+    "<|im_start|>assistant",
+    "<|im_start|>",
+    "<|im_end|>",
+    "<|assistant|>",
+    "<|user|>",
+    "<|system|>",
+    "<|end|>",
+    "<|endoftext|>",
+    "[/INST]",
+    "[INST]",
+    "<</SYS>>",
+    "<<SYS>>",
+];
+
+/// Sequence markers that are also legitimate HTML, so only stripped from the ends.
+const EDGES: &[&str] = &["<s>", "</s>"];
+
+/// The markers to strip, or `None` when `STRIP_ARTIFACTS` is off.
+pub fn from_env(env: &Env) -> Option<Vec<String>> {
+    if !config::flag(env, "STRIP_ARTIFACTS", true) {
+        return None;
+    }
+    let extra = config::var(env, "STRIP_ARTIFACTS_EXTRA").unwrap_or_default();
+    Some(
+        extra
+            .split(',')
+            .map(str::trim)
+            .filter(|marker| !marker.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// `text` without artifacts, or `None` if there were none.
+pub fn strip(text: &str, extra: &[String]) -> Option<String> {
+    let mut cleaned = text.to_string();
+    for marker in ANYWHERE.iter().copied().chain(extra.iter().map(String::as_str)) {
+        if cleaned.contains(marker) {
+            cleaned = cleaned.replace(marker, "");
+        }
+    }
+
+    loop {
+        let trimmed = cleaned.trim();
+        let edge = EDGES.iter().find(|m| trimmed.starts_with(**m) || trimmed.ends_with(**m));
+        let Some(marker) = edge else {
+            break;
+        };
+        let trimmed = trimmed.strip_prefix(marker).unwrap_or(trimmed);
+        cleaned = trimmed.strip_suffix(marker).unwrap_or(trimmed).to_string();
+    }
+
+    let cleaned = cleaned.trim();
+    (cleaned != text.trim()).then(|| cleaned.to_string())
+}
+
+/// Strip artifacts from a text result (a bare string or `{ "response": "..." }`).
+/// Returns whether anything was removed.
+pub fn strip_result(result: &mut Value, extra: &[String]) -> bool {
+    let text = if result.is_string() {
+        Some(result)
+    } else {
+        result.get_mut("response").filter(|r| r.is_string())
+    };
+    let Some(text) = text else {
+        return false;
+    };
+
+    match text.as_str().and_then(|t| strip(t, extra)) {
+        Some(cleaned) => {
+            *text = Value::String(cleaned);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strips_template_tokens_anywhere() {
+        assert_eq!(strip("<|assistant|>Hello there<|eot_id|>", &[]), Some("Hello there".to_string()));
+        assert_eq!(
+            strip("<|start_header_id|>assistant<|end_header_id|>\n\nHi [/INST]", &[]),
+            Some("Hi".to_string())
+        );
+        assert_eq!(strip("Answer<|im_end|>\n<|im_start|>assistant more", &[]), Some("Answer\n more".to_string()));
+    }
+
+    #[test]
+    fn sequence_markers_only_go_at_the_edges() {
+        assert_eq!(strip("<s> text </s>", &[]), Some("text".to_string()));
+        assert_eq!(strip("Use <s> for strikethrough", &[]), None);
+    }
+
+    #[test]
+    fn clean_text_is_left_alone() {
+        assert_eq!(strip("Just an answer.", &[]), None);
+        assert_eq!(strip("  padded  ", &[]), None);
+    }
+
+    #[test]
+    fn extra_markers_are_stripped_too() {
+        let extra = vec!["<|custom|>".to_string()];
+        assert_eq!(strip("<|custom|>Hi", &extra), Some("Hi".to_string()));
+        assert_eq!(strip("<|custom|>Hi", &[]), None);
+    }
+
+    #[test]
+    fn strip_result_handles_strings_and_responses() {
+        let mut result = json!("Hi<|eot_id|>");
+        assert!(strip_result(&mut result, &[]));
+        assert_eq!(result, json!("Hi"));
+
+        let mut result = json!({ "response": "[INST]Hi", "usage": {} });
+        assert!(strip_result(&mut result, &[]));
+        assert_eq!(result, json!({ "response": "Hi", "usage": {} }));
+
+        let mut result = json!({ "data": [1, 2] });
+        assert!(!strip_result(&mut result, &[]));
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Circuit breaker in front of the AI binding, shared by all isolates through a
+//! Durable Object bound as `CIRCUIT_BREAKER`. After `BREAKER_THRESHOLD`
+//! consecutive backend failures within `BREAKER_WINDOW_MS` the circuit opens and
+//! calls fail fast for `BREAKER_COOLDOWN_MS`; then a single probe call is let
+//! through, and its outcome closes or re-opens the circuit. The state is kept
+//! in the object's storage, so eviction doesn't reset it.
+//!
+//! Without the binding every call is allowed. If the Durable Object itself
+//! can't be reached the breaker fails open rather than blocking inference.
+
+use std::cell::{Cell, RefCell};
+use serde::{Deserialize, Serialize};
+use worker::*;
+use crate::ai::AiError;
+use crate::config;
+
+const BINDING: &str = "CIRCUIT_BREAKER";
+
+/// One breaker for the whole deployment.
+const INSTANCE: &str = "ai";
+
+/// Storage key the circuit is persisted under, so it survives eviction.
+const STORAGE_KEY: &str = "circuit";
+
+const CLOSED: Circuit = Circuit::Closed { failures: 0, first_failure_ms: 0 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Circuit {
+    Closed { failures: u32, first_failure_ms: u64 },
+    Open { until_ms: u64 },
+    /// Cooldown is over and one probe call is in flight. A probe that hasn't
+    /// reported back by the deadline (its isolate died, or the request was
+    /// abandoned) is written off and the next call probes instead.
+    HalfOpen { probe_deadline_ms: u64 },
+}
+
+thread_local! {
+    /// Whether the last answer from the breaker said it was closed with no
+    /// failures counted, in which case a success has nothing to reset.
+    static CLEAN: Cell<bool> = const { Cell::new(false) };
+}
+
+#[durable_object]
+pub struct CircuitBreaker {
+    state: State,
+    /// Cached copy of the stored circuit, loaded on first use.
+    circuit: RefCell<Option<Circuit>>,
+    env: Env,
+}
+
+impl CircuitBreaker {
+    async fn load(&self) -> Circuit {
+        if let Some(circuit) = *self.circuit.borrow() {
+            return circuit;
+        }
+        let stored = match self.state.storage().get::<Circuit>(STORAGE_KEY).await {
+            Ok(stored) => stored.unwrap_or(CLOSED),
+            Err(e) => {
+                console_warn!("Failed to load circuit state, starting closed: {}", e);
+                CLOSED
+            }
+        };
+        *self.circuit.borrow_mut() = Some(stored);
+        stored
+    }
+
+    async fn save(&self, circuit: Circuit) -> Result<()> {
+        *self.circuit.borrow_mut() = Some(circuit);
+        self.state.storage().put(STORAGE_KEY, circuit).await
+    }
+}
+
+impl DurableObject for CircuitBreaker {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            state,
+            circuit: RefCell::new(None),
+            env,
+        }
+    }
+
+    async fn fetch(&self, req: Request) -> Result<Response> {
+        let now = Date::now().as_millis();
+        let circuit = self.load().await;
+        // No probe outlives a request, so by then it has reported or never will
+        let probe = Circuit::HalfOpen { probe_deadline_ms: now + config::ai_timeout_ms(&self.env) };
+
+        match req.path().as_str() {
+            "/check" => {
+                let (next, retry_after_ms) = match circuit {
+                    Circuit::Closed { .. } => (circuit, None),
+                    Circuit::Open { until_ms } if now >= until_ms => (probe, None),
+                    Circuit::Open { until_ms } => (circuit, Some(until_ms - now)),
+                    Circuit::HalfOpen { probe_deadline_ms } if now >= probe_deadline_ms => {
+                        console_warn!("AI circuit breaker probe never reported back; probing again");
+                        (probe, None)
+                    }
+                    // Only the probe goes through until it reports back
+                    Circuit::HalfOpen { probe_deadline_ms } => (circuit, Some(probe_deadline_ms - now)),
+                };
+                if next != circuit {
+                    self.save(next).await?;
+                }
+                Response::from_json(&serde_json::json!({
+                    "allow": retry_after_ms.is_none(),
+                    "retry_after_ms": retry_after_ms,
+                    "clean": next == CLOSED,
+                }))
+            }
+            "/success" => {
+                if circuit != CLOSED {
+                    self.save(CLOSED).await?;
+                }
+                Response::empty()
+            }
+            "/failure" => {
+                let threshold = config::number(&self.env, "BREAKER_THRESHOLD", 5u32).max(1);
+                let window_ms = config::number(&self.env, "BREAKER_WINDOW_MS", 60_000u64);
+                let cooldown_ms = config::number(&self.env, "BREAKER_COOLDOWN_MS", 30_000u64);
+
+                let (failures, first_failure_ms) = match circuit {
+                    Circuit::Closed { failures, first_failure_ms } if now - first_failure_ms <= window_ms => {
+                        (failures + 1, first_failure_ms)
+                    }
+                    Circuit::Closed { .. } => (1, now),
+                    // A failed probe re-opens straight away
+                    Circuit::HalfOpen { .. } => (threshold, now),
+                    // Stragglers that were let through before the circuit opened
+                    Circuit::Open { .. } => return Response::empty(),
+                };
+
+                let next = if failures >= threshold {
+                    console_warn!("Opening AI circuit breaker for {}ms after {} failures", cooldown_ms, failures);
+                    Circuit::Open { until_ms: now + cooldown_ms }
+                } else {
+                    Circuit::Closed { failures, first_failure_ms }
+                };
+                self.save(next).await?;
+                Response::empty()
+            }
+            _ => Response::error("Not found", 404),
+        }
+    }
+}
+
+async fn send(env: &Env, path: &str) -> Result<Response> {
+    let stub = env.durable_object(BINDING)?.id_from_name(INSTANCE)?.get_stub()?;
+    stub.fetch_with_str(&format!("https://circuit-breaker{}", path)).await
+}
+
+/// Fail fast with `AiError::Unavailable` while the circuit is open.
+pub async fn check(env: &Env) -> std::result::Result<(), AiError> {
+    if env.durable_object(BINDING).is_err() {
+        return Ok(());
+    }
+
+    let verdict = async {
+        let mut response = send(env, "/check").await?;
+        response.json::<serde_json::Value>().await
+    }
+    .await;
+
+    match verdict {
+        Ok(verdict) if verdict.get("allow").and_then(|v| v.as_bool()) == Some(false) => {
+            let retry_after_ms = verdict.get("retry_after_ms").and_then(|v| v.as_u64()).unwrap_or(0);
+            Err(AiError::Unavailable(format!(
+                "backend temporarily unavailable; retry in {}ms",
+                retry_after_ms.max(1000)
+            )))
+        }
+        Ok(verdict) => {
+            CLEAN.with(|c| c.set(verdict.get("clean").and_then(|v| v.as_bool()) == Some(true)));
+            Ok(())
+        }
+        Err(e) => {
+            CLEAN.with(|c| c.set(false));
+            console_warn!("Circuit breaker unreachable, allowing call: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Tell the breaker how a backend call went. Successes are only sent when the
+/// breaker last had something to reset, so a healthy backend costs one
+/// round trip per call rather than two.
+pub async fn report(env: &Env, ok: bool) {
+    if env.durable_object(BINDING).is_err() || (ok && CLEAN.with(|c| c.get())) {
+        return;
+    }
+    CLEAN.with(|c| c.set(ok));
+    if let Err(e) = send(env, if ok { "/success" } else { "/failure" }).await {
+        console_warn!("Failed to report to circuit breaker: {}", e);
+    }
+}
@@ -1,8 +1,14 @@
 // Copyright (C) 2026 Jade
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::cell::Cell;
 use worker::*;
-use crate::ai::{ModelRegistry, AiResponse};
+use crate::ai::{ModelRegistry, AiError, AiResponse, AiStream, TokenUsage};
+use crate::ai::{breaker, media};
+use crate::ai::sanitize::{self, SanitizeMode};
+use crate::ai::formatters::{self, FormatOptions};
+use crate::ai::models::{CostBreakdown, ModelCategory, ModelInfo};
+use crate::config;
 use wasm_bindgen::prelude::*;
 use js_sys::Promise;
 
@@ -17,24 +23,304 @@ extern "C" {
     fn run(this: &CloudflareAI, model: &str, input: &JsValue) -> Promise;
 }
 
+thread_local! {
+    /// When a backend call last succeeded in this isolate, for `/ready`.
+    static LAST_SUCCESS_MS: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
 pub struct AiBridge;
 
 impl AiBridge {
+    /// Run one inference. With `deadline_ms` (a Unix time in milliseconds), no
+    /// retry is started that would have to wait past it.
     pub async fn run_inference(
         env: &Env,
         model_id: &str,
-        input: serde_json::Value,
-    ) -> Result<AiResponse> {
-        let model = ModelRegistry::get_model(model_id)
-            .ok_or_else(|| Error::RustError(format!("Unknown model: {}", model_id)))?;
+        mut input: serde_json::Value,
+        deadline_ms: Option<u64>,
+    ) -> std::result::Result<AiResponse, AiError> {
+        let model = Self::resolve_model(env, model_id, &mut input)?;
+
+        let prompt_tokens = ModelInfo::estimate_prompt_tokens(&input);
+        let (ai_input, estimated_neurons, mut meta) = Self::prepare_input(env, &model, input).await?;
+
+        console_log!("Calling AI with model: {}, input: {}", model_id, serde_json::to_string(&ai_input).unwrap_or_default());
+
+        // Neurons are attributed once, from the attempt that succeeded; failed
+        // attempts don't report any usage
+        let (result, attempts, latency_ms) = Self::call_ai_with_retry(env, model_id, &ai_input, deadline_ms).await?;
+        if attempts > 1 {
+            meta.insert("attempts".to_string(), serde_json::json!(attempts));
+        }
+        meta.insert("latency_ms".to_string(), serde_json::json!(latency_ms));
+
+        // Parse the result; models may answer with raw bytes (a PNG stream, an
+        // ArrayBuffer) instead of JSON. Images are normalized to the
+        // `{ image: base64 }` shape other image models use, anything else becomes
+        // `{ base64, bytes }`, and `_meta.binary` says it happened
+        let binary = if media::is_stream(&result) {
+            Some(media::read_all(&result).await?)
+        } else {
+            media::binary_bytes(&result)
+        };
+        let ai_result: serde_json::Value = if let Some(bytes) = binary {
+            // Only the shape: the payload itself can be megabytes of base64
+            console_log!("AI result: {} bytes of binary output", bytes.len());
+            meta.insert("binary".to_string(), serde_json::json!({ "bytes": bytes.len() }));
+            if model.category == ModelCategory::Image {
+                serde_json::json!({ "image": media::encode_base64(&bytes) })
+            } else {
+                serde_json::json!({ "base64": media::encode_base64(&bytes), "bytes": bytes.len() })
+            }
+        } else {
+            let parsed: serde_json::Value = serde_wasm_bindgen::from_value(result)
+                .map_err(|e| Error::RustError(format!("Failed to parse AI result: {}", e)))?;
+            console_log!("AI result: {}", serde_json::to_string(&parsed).unwrap_or_default());
+            parsed
+        };
+
+        // Extract neurons_used from response, fallback to estimate
+        let reported_neurons = ai_result.get("neurons_used")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32);
+        let neurons_used = reported_neurons.unwrap_or(estimated_neurons);
+
+        // Prefer the backend's token counts; estimate from text lengths otherwise
+        let usage = ai_result.get("usage")
+            .and_then(TokenUsage::from_reported)
+            .unwrap_or_else(|| TokenUsage {
+                prompt_tokens,
+                completion_tokens: ai_result.get("response")
+                    .and_then(|r| r.as_str())
+                    .map_or(0, |r| (r.len() / 4) as u32),
+            });
+
+        Ok(AiResponse {
+            result: ai_result,
+            neurons_used,
+            estimated_neurons,
+            neurons_reported: reported_neurons.is_some(),
+            model_used: model.id,
+            category: Some(model.category),
+            usage: Some(usage),
+            meta,
+        })
+    }
+
+    /// Like `run_inference`, but asks the backend for `{ stream: true }` output and
+    /// hands back the raw `ReadableStream` of server-sent events.
+    pub async fn run_stream(
+        env: &Env,
+        model_id: &str,
+        mut input: serde_json::Value,
+    ) -> std::result::Result<AiStream, AiError> {
+        let model = Self::resolve_model(env, model_id, &mut input)?;
+
+        let (mut ai_input, estimated_neurons, _) = Self::prepare_input(env, &model, input).await?;
+        if let Some(obj) = ai_input.as_object_mut() {
+            obj.insert("stream".to_string(), serde_json::Value::Bool(true));
+        }
+
+        console_log!("Streaming AI with model: {}, input: {}", model_id, serde_json::to_string(&ai_input).unwrap_or_default());
+
+        let (body, _, _) = Self::call_ai_with_retry(env, model_id, &ai_input, None).await?;
+
+        Ok(AiStream {
+            body,
+            estimated_neurons,
+            model_used: model.id,
+        })
+    }
+
+    /// The neuron estimate `run_inference` would make for this call and how it was
+    /// reached, without calling the model. `neurons` has `NEURON_MULTIPLIER` applied,
+    /// like the figures in tool results; `raw_neurons` is what budgets count.
+    pub fn explain_cost(
+        env: &Env,
+        model_id: &str,
+        mut input: serde_json::Value,
+    ) -> std::result::Result<serde_json::Value, AiError> {
+        let model = Self::resolve_model(env, model_id, &mut input)?;
+
+        let raw = input.get("__raw").and_then(|v| v.as_bool()).unwrap_or(false);
+        let breakdown = if raw {
+            // Mirrors `prepare_input`: raw passthrough is charged the base cost
+            CostBreakdown {
+                input_tokens: None,
+                output_tokens: None,
+                factors: serde_json::json!({ "raw_passthrough": true, "base_neurons": model.base_neurons }),
+                neurons: model.base_neurons,
+            }
+        } else {
+            model.cost_breakdown(&input)
+        };
+
+        Ok(serde_json::json!({
+            "model": model.id,
+            "category": model.category,
+            "input_tokens": breakdown.input_tokens,
+            "output_tokens": breakdown.output_tokens,
+            "factors": breakdown.factors,
+            "raw_neurons": breakdown.neurons,
+            "neuron_multiplier": config::neuron_multiplier(env),
+            "neurons": config::billed_neurons(env, breakdown.neurons),
+        }))
+    }
+
+    /// Look up the model, honoring an optional `__category` argument that overrides
+    /// the guessed category of dynamic (non-curated) models. Ids that don't look like
+    /// Workers AI models are rejected unless `PERMISSIVE_MODELS` is set, and with
+    /// `DYNAMIC_MODELS` off only curated ids are accepted.
+    fn resolve_model(env: &Env, model_id: &str, input: &mut serde_json::Value) -> std::result::Result<ModelInfo, AiError> {
+        let hint = match input.as_object_mut().and_then(|obj| obj.remove("__category")) {
+            Some(value) => Some(serde_json::from_value::<ModelCategory>(value).map_err(|_| {
+                AiError::InvalidInput(
+                    "'__category' must be one of: llm, embedding, image, audio, classification, image_to_text".to_string(),
+                )
+            })?),
+            None => None,
+        };
+
+        if !ModelRegistry::dynamic_models(env) && !ModelRegistry::is_curated(model_id) {
+            return Err(AiError::UnknownModel(model_id.to_string()));
+        }
+
+        if config::flag(env, "PERMISSIVE_MODELS", false) {
+            return Ok(ModelRegistry::permissive_model(model_id, hint));
+        }
+
+        ModelRegistry::get_model_with_hint(model_id, hint)
+            .ok_or_else(|| AiError::UnknownModel(model_id.to_string()))
+    }
+
+    /// Estimate the cost and transform the caller's arguments into the shape
+    /// `AI.run` expects. `__raw: true` skips formatting and forwards the rest verbatim.
+    async fn prepare_input(
+        env: &Env,
+        model: &ModelInfo,
+        mut input: serde_json::Value,
+    ) -> std::result::Result<(serde_json::Value, u32, serde_json::Map<String, serde_json::Value>), AiError> {
+        let mut meta = serde_json::Map::new();
+
+        // Screened before anything else, so `__raw` can't be used to skip it
+        if let Some(mode) = SanitizeMode::from_env(env) {
+            let matched = sanitize::sanitize_input(&mut input, mode)?;
+            if !matched.is_empty() {
+                console_warn!("Neutralized possible prompt injection for {}: {:?}", model.id, matched);
+                meta.insert("sanitized".to_string(), serde_json::json!(matched));
+            }
+        }
+
+        let raw = input.as_object_mut()
+            .and_then(|obj| obj.remove("__raw"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        if raw {
+            console_warn!("Raw passthrough requested for {}: skipping input formatting", model.id);
+            meta.insert("raw_passthrough".to_string(), serde_json::Value::Bool(true));
+            return Ok((input, model.base_neurons, meta));
+        }
 
         let estimated_neurons = model.estimate_neurons(&input);
 
+        // Image inputs arrive as URLs or base64; the backend wants a byte array
+        if matches!(model.category, ModelCategory::Classification | ModelCategory::ImageToText) {
+            if let Some(image) = input.get("image") {
+                let bytes = media::load_image(env, image).await?;
+                input["image"] = serde_json::json!(bytes);
+            }
+        }
+
+        // Audio given as a URL is downloaded; other audio shapes pass through
+        if model.category == ModelCategory::Audio {
+            if let Some(audio) = input.get("audio") {
+                if let Some(bytes) = media::load_audio_url(env, audio).await? {
+                    input["audio"] = serde_json::json!(bytes);
+                }
+            }
+        }
+
         // Transform input to match Cloudflare AI API format
-        let ai_input = Self::format_input_for_model(model_id, input)?;
+        let options = FormatOptions::from_env(env);
+        let ai_input = formatters::format_input(model, input, &options, &mut meta)?;
 
-        console_log!("Calling AI with model: {}, input: {}", model_id, serde_json::to_string(&ai_input).unwrap_or_default());
+        Ok((ai_input, estimated_neurons, meta))
+    }
+
+    /// `call_ai`, retrying capacity and rate-limit failures with jittered exponential
+    /// backoff up to `AI_MAX_ATTEMPTS` (default 3) times. Returns the result, the
+    /// number of attempts it took, and the milliseconds spent waiting on the
+    /// backend (backoff delays excluded). Fails fast while the circuit breaker is
+    /// open, and gives up instead of retrying when the backoff would run past
+    /// `deadline_ms`.
+    async fn call_ai_with_retry(
+        env: &Env,
+        model_id: &str,
+        ai_input: &serde_json::Value,
+        deadline_ms: Option<u64>,
+    ) -> std::result::Result<(JsValue, u32, u64), AiError> {
+        breaker::check(env).await?;
+        let max_attempts = config::number(env, "AI_MAX_ATTEMPTS", 3u32).clamp(1, 5);
+
+        let mut attempt = 1;
+        let mut latency_ms = 0;
+        loop {
+            let started = Date::now().as_millis();
+            let outcome = Self::call_ai(env, model_id, ai_input).await;
+            latency_ms += Date::now().as_millis().saturating_sub(started);
+
+            // Jitter spreads out retries from concurrent requests so they
+            // don't hit a struggling backend in lockstep
+            let base = 250 * 2u64.pow(attempt - 1);
+            let backoff = base + (js_sys::Math::random() * (base / 2) as f64) as u64;
+            let in_time = deadline_ms.is_none_or(|deadline| Date::now().as_millis() + backoff < deadline);
+
+            match outcome {
+                Ok(result) => {
+                    LAST_SUCCESS_MS.with(|t| t.set(Some(Date::now().as_millis())));
+                    breaker::report(env, true).await;
+                    return Ok((result, attempt, latency_ms));
+                }
+                Err(e) if attempt < max_attempts && in_time && Self::is_retryable(&e) => {
+                    console_warn!("{} attempt {} failed ({}); retrying in {}ms", model_id, attempt, e, backoff);
+                    Delay::from(std::time::Duration::from_millis(backoff)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    // Every backend failure counts against the circuit except
+                    // the backend refusing the caller's input, which isn't a
+                    // sign of trouble but isn't a success either
+                    if !is_caller_error(&e.to_string()) {
+                        breaker::report(env, false).await;
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
 
+    /// Whether the `AI` binding is configured at all.
+    pub fn has_binding(env: &Env) -> bool {
+        let env_js = unsafe { &*(env as *const Env as *const JsValue) };
+        js_sys::Reflect::get(env_js, &JsValue::from_str("AI")).is_ok_and(|ai| ai.is_object())
+    }
+
+    /// When a backend call last succeeded in this isolate, if one has.
+    pub fn last_success_ms() -> Option<u64> {
+        LAST_SUCCESS_MS.with(|t| t.get())
+    }
+
+    /// Capacity and rate-limit errors are worth retrying; bad input isn't.
+    fn is_retryable(error: &Error) -> bool {
+        let message = error.to_string().to_ascii_lowercase();
+        ["capacity", "429", "too many requests", "temporarily", "overloaded"]
+            .iter()
+            .any(|needle| message.contains(needle))
+    }
+
+    /// Call `env.AI.run(model, input)` and await the returned promise.
+    async fn call_ai(env: &Env, model_id: &str, ai_input: &serde_json::Value) -> Result<JsValue> {
         // Get AI binding from environment
         // Access the env as a JsValue to get the AI binding
         unsafe {
@@ -45,13 +331,13 @@ impl AiBridge {
                 .map_err(|_| Error::RustError("Failed to get AI binding from env".to_string()))?;
 
             // Serialize input using JSON.parse for guaranteed correct format
-            let input_json = serde_json::to_string(&ai_input)
+            let input_json = serde_json::to_string(ai_input)
                 .map_err(|e| Error::RustError(format!("Failed to serialize to JSON: {}", e)))?;
 
             console_log!("JSON input: {}", input_json);
 
             let input_js = js_sys::JSON::parse(&input_json)
-                .map_err(|e| Error::RustError(format!("Failed to parse JSON: {:?}", e)))?;
+                .map_err(|e| js_error("Failed to parse JSON", e))?;
 
             // Get the run method
             let run_fn = js_sys::Reflect::get(&ai_binding, &JsValue::from_str("run"))
@@ -62,60 +348,82 @@ impl AiBridge {
             // Call AI.run(model, input) with the AI binding as `this`
             let model_js = JsValue::from_str(model_id);
             let promise = run_fn.call2(&ai_binding, &model_js, &input_js)
-                .map_err(|e| Error::RustError(format!("Failed to call AI.run: {:?}", e)))?
+                .map_err(|e| js_error("Failed to call AI.run", e))?
                 .dyn_into::<Promise>()
                 .map_err(|_| Error::RustError("AI.run did not return a promise".to_string()))?;
 
-            let result = wasm_bindgen_futures::JsFuture::from(promise).await
-                .map_err(|e| Error::RustError(format!("AI inference failed: {:?}", e)))?;
+            // Callers already prefix backend failures with "AI inference failed"
+            wasm_bindgen_futures::JsFuture::from(promise).await
+                .map_err(|e| js_error("", e))
+        }
+    }
+}
 
-            // Parse the result
-            let ai_result: serde_json::Value = serde_wasm_bindgen::from_value(result)
-                .map_err(|e| Error::RustError(format!("Failed to parse AI result: {}", e)))?;
+/// Whether a backend error `message` is the backend rejecting the caller's
+/// input (a 400/413/422 or a validation message) rather than failing.
+fn is_caller_error(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    let status = message
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|token| matches!(token, "400" | "413" | "422"));
+    status
+        || ["bad request", "invalid input", "incomplete input", "required properties", "validation"]
+            .iter()
+            .any(|needle| message.contains(needle))
+}
 
-            console_log!("AI result: {}", serde_json::to_string(&ai_result).unwrap_or_default());
+/// Longest error message passed on to clients; the full value is logged.
+const MAX_ERROR_CHARS: usize = 300;
 
-            // Extract neurons_used from response, fallback to estimate
-            let neurons_used = ai_result.get("neurons_used")
-                .and_then(|v| v.as_u64())
-                .map(|v| v as u32)
-                .unwrap_or(estimated_neurons);
+/// Turn a JS rejection into a clean, client-safe error: just the message of an
+/// `Error` (or the string that was thrown), without stack frames, cut to
+/// `MAX_ERROR_CHARS`. The raw value goes to the log.
+pub(crate) fn js_error(context: &str, value: JsValue) -> Error {
+    console_error!("{}: {:?}", if context.is_empty() { "AI binding error" } else { context }, value);
 
-            Ok(AiResponse {
-                result: ai_result,
-                neurons_used,
-            })
-        }
+    let raw = if let Some(error) = value.dyn_ref::<js_sys::Error>() {
+        String::from(error.message())
+    } else if let Some(text) = value.as_string() {
+        text
+    } else {
+        js_sys::JSON::stringify(&value)
+            .ok()
+            .and_then(|s| s.as_string())
+            .unwrap_or_default()
+    };
+
+    let message: String = raw
+        .lines()
+        .take_while(|line| !line.trim_start().starts_with("at "))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim()
+        .chars()
+        .take(MAX_ERROR_CHARS)
+        .collect();
+    let message = if message.is_empty() { "unknown error".to_string() } else { message };
+
+    Error::RustError(if context.is_empty() { message } else { format!("{}: {}", context, message) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_input_is_a_caller_error() {
+        assert!(is_caller_error("AiError: 5006: Error: required properties at '/' are 'prompt'"));
+        assert!(is_caller_error("3010: Invalid or incomplete input for the model"));
+        assert!(is_caller_error("HTTP 400 Bad Request"));
+        assert!(is_caller_error("status 422"));
     }
 
-    fn format_input_for_model(model_id: &str, input: serde_json::Value) -> Result<serde_json::Value> {
-        // Format input according to model type
-        if model_id.contains("llama") || model_id.contains("mistral") {
-            // Text generation models - use simple prompt format
-            let prompt = input.get("prompt")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| Error::RustError("Missing 'prompt' field".to_string()))?;
-
-            Ok(serde_json::json!({ "prompt": prompt }))
-        } else if model_id.contains("bge") {
-            // Embedding models expect { text: "..." } or { text: [...] }
-            let text = input.get("text")
-                .ok_or_else(|| Error::RustError("Missing 'text' field".to_string()))?;
-
-            Ok(serde_json::json!({ "text": text }))
-        } else if model_id.contains("stable-diffusion") {
-            // Image generation models expect { prompt: "..." }
-            let prompt = input.get("prompt")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| Error::RustError("Missing 'prompt' field".to_string()))?;
-
-            Ok(serde_json::json!({ "prompt": prompt }))
-        } else if model_id.contains("whisper") {
-            // Whisper expects { audio: [...] }
-            Ok(input)
-        } else {
-            // Default: pass through
-            Ok(input)
-        }
+    #[test]
+    fn backend_trouble_is_not_a_caller_error() {
+        assert!(!is_caller_error("3040: Capacity temporarily exceeded, please try again"));
+        assert!(!is_caller_error("429 Too Many Requests"));
+        assert!(!is_caller_error("InferenceUpstreamError: 500 internal server error"));
+        assert!(!is_caller_error("4006: you have used up your daily free allocation of 10,000 neurons"));
+        assert!(!is_caller_error("network connection lost"));
     }
 }
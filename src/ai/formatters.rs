@@ -0,0 +1,316 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Turning a caller's tool arguments into the input shape `AI.run` expects.
+//! Each category has a formatter; `OVERRIDES` swaps in a different one for
+//! models whose id matches, so new model shapes are a one-line change.
+
+use std::collections::HashMap;
+use worker::{console_warn, Env};
+use crate::ai::AiError;
+use crate::ai::models::{ModelCategory, ModelInfo};
+use crate::config;
+use crate::tasks::translate;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// Deployment-wide settings that shape how arguments are formatted.
+pub struct FormatOptions {
+    /// Reject out-of-range values instead of clamping them (`STRICT_PARAMS`).
+    pub strict: bool,
+    /// Prepended to LLM calls unless the caller sets `ignore_system` (`SYSTEM_PROMPT`).
+    pub system_prompt: Option<String>,
+    /// ISO 639-1 code Whisper models use when the caller gives no `language` (`DEFAULT_AUDIO_LANGUAGE`).
+    pub audio_language: Option<String>,
+    /// Per-model text wrapped around LLM prompts (`PROMPT_WRAPPERS`).
+    pub prompt_wrappers: HashMap<String, PromptWrapper>,
+}
+
+/// Text added around a model's prompts. The flat `prompt` is always wrapped; the
+/// last user message of `messages` only when `messages` is set.
+#[derive(Debug, Default, Deserialize)]
+pub struct PromptWrapper {
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub suffix: Option<String>,
+    #[serde(default)]
+    pub messages: bool,
+}
+
+impl PromptWrapper {
+    fn wrap(&self, text: &str) -> String {
+        [self.prefix.as_deref(), Some(text), self.suffix.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl FormatOptions {
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            strict: config::flag(env, "STRICT_PARAMS", false),
+            system_prompt: config::var(env, "SYSTEM_PROMPT"),
+            audio_language: Self::audio_language(env),
+            prompt_wrappers: Self::prompt_wrappers(env),
+        }
+    }
+
+    fn prompt_wrappers(env: &Env) -> HashMap<String, PromptWrapper> {
+        let Some(raw) = config::var(env, "PROMPT_WRAPPERS") else {
+            return HashMap::new();
+        };
+        serde_json::from_str(&raw).unwrap_or_else(|e| {
+            console_warn!("Ignoring PROMPT_WRAPPERS: {}", e);
+            HashMap::new()
+        })
+    }
+
+    fn audio_language(env: &Env) -> Option<String> {
+        let code = config::var(env, "DEFAULT_AUDIO_LANGUAGE")?.trim().to_ascii_lowercase();
+        if translate::language_name(&code).is_some() {
+            Some(code)
+        } else {
+            console_warn!("Ignoring DEFAULT_AUDIO_LANGUAGE={:?}: not a known ISO 639-1 code", code);
+            None
+        }
+    }
+}
+
+/// What a formatter gets to look at besides the input itself.
+pub struct FormatContext<'a> {
+    pub model: &'a ModelInfo,
+    pub options: &'a FormatOptions,
+    /// Notes about adjustments made to the input, surfaced as `_meta`.
+    pub meta: &'a mut Map<String, Value>,
+}
+
+pub trait InputFormatter: Sync {
+    fn format(&self, input: Value, ctx: &mut FormatContext) -> Result<Value, AiError>;
+}
+
+/// Model-specific formatters, matched by substring of the model id within a category.
+const OVERRIDES: &[(ModelCategory, &str, &dyn InputFormatter)] = &[
+    (ModelCategory::Llm, "llama", &ChatFormatter),
+    (ModelCategory::Llm, "mistral", &ChatFormatter),
+    (ModelCategory::Audio, "whisper", &WhisperFormatter),
+];
+
+fn formatter_for(model: &ModelInfo) -> &'static dyn InputFormatter {
+    let overridden = OVERRIDES
+        .iter()
+        .find(|(category, pattern, _)| *category == model.category && model.id.contains(pattern));
+    if let Some((_, _, formatter)) = overridden {
+        return *formatter;
+    }
+
+    match model.category {
+        ModelCategory::Embedding => &EmbeddingFormatter,
+        ModelCategory::Image => &ImageFormatter,
+        ModelCategory::Classification => &ClassificationFormatter,
+        ModelCategory::ImageToText => &ImageToTextFormatter,
+        // Other LLMs take the caller's arguments as-is, once `format_input` has
+        // added the system prompt and wrapper every LLM gets; audio models other
+        // than Whisper don't all take a `language`
+        ModelCategory::Llm | ModelCategory::Audio => &PassThrough,
+    }
+}
+
+/// Format `input` for `model`, recording any adjustments in `meta`. The
+/// LLM-wide settings (`PROMPT_WRAPPERS`, `SYSTEM_PROMPT`) are applied here
+/// rather than in a formatter, so models without an override get them too.
+pub fn format_input(
+    model: &ModelInfo,
+    mut input: Value,
+    options: &FormatOptions,
+    meta: &mut Map<String, Value>,
+) -> Result<Value, AiError> {
+    if model.category == ModelCategory::Llm {
+        let mut opt_out = |key: &str| {
+            input.as_object_mut()
+                .and_then(|obj| obj.remove(key))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false)
+        };
+        let ignore_wrapper = opt_out("ignore_wrapper");
+        let ignore_system = opt_out("ignore_system");
+
+        if let Some(wrapper) = options.prompt_wrappers.get(&model.id).filter(|_| !ignore_wrapper) {
+            apply_wrapper(wrapper, &mut input);
+        }
+        if let Some(system) = options.system_prompt.as_deref().filter(|_| !ignore_system) {
+            apply_system_prompt(system, &mut input);
+        }
+    }
+
+    let mut ctx = FormatContext { model, options, meta };
+    formatter_for(model).format(input, &mut ctx)
+}
+
+/// Wrap the caller's flat `prompt`, and with `messages: true` the content of the
+/// last user message.
+fn apply_wrapper(wrapper: &PromptWrapper, input: &mut Value) {
+    if let Some(prompt) = input.get("prompt").and_then(|p| p.as_str()) {
+        input["prompt"] = json!(wrapper.wrap(prompt));
+    }
+
+    if !wrapper.messages {
+        return;
+    }
+    let last_user = input.get_mut("messages")
+        .and_then(|m| m.as_array_mut())
+        .and_then(|messages| messages.iter_mut().rev().find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user")));
+    if let Some(message) = last_user {
+        if let Some(content) = message.get("content").and_then(|c| c.as_str()) {
+            message["content"] = json!(wrapper.wrap(content));
+        }
+    }
+}
+
+/// Put the operator's system prompt first: as a leading `system` message, or
+/// above a flat `prompt`.
+fn apply_system_prompt(system: &str, input: &mut Value) {
+    if let Some(messages) = input.get_mut("messages").and_then(|m| m.as_array_mut()) {
+        messages.insert(0, json!({ "role": "system", "content": system }));
+    } else if let Some(prompt) = input.get("prompt").and_then(|p| p.as_str()) {
+        input["prompt"] = json!(format!("{}\n\n{}", system, prompt));
+    }
+}
+
+/// Copy the listed keys from `input` into `formatted` when present.
+fn forward(input: &Value, formatted: &mut Value, keys: &[&str]) {
+    for key in keys {
+        if let Some(value) = input.get(*key) {
+            formatted[*key] = value.clone();
+        }
+    }
+}
+
+struct PassThrough;
+
+impl InputFormatter for PassThrough {
+    fn format(&self, input: Value, _ctx: &mut FormatContext) -> Result<Value, AiError> {
+        Ok(input)
+    }
+}
+
+/// Text generation: chat `messages` if given, otherwise a simple prompt.
+struct ChatFormatter;
+
+impl InputFormatter for ChatFormatter {
+    fn format(&self, input: Value, _ctx: &mut FormatContext) -> Result<Value, AiError> {
+        let mut formatted = if let Some(messages) = input.get("messages") {
+            let messages = messages.as_array()
+                .ok_or_else(|| AiError::InvalidInput("'messages' must be an array".to_string()))?;
+            json!({ "messages": messages })
+        } else {
+            let prompt = input.get("prompt")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| AiError::InvalidInput("Missing 'prompt' field".to_string()))?;
+            json!({ "prompt": prompt })
+        };
+
+        forward(&input, &mut formatted, &["max_tokens", "temperature", "top_p", "seed"]);
+        Ok(formatted)
+    }
+}
+
+/// Embedding models expect `{ text: "..." }` or `{ text: [...] }`, with an optional `pooling`.
+struct EmbeddingFormatter;
+
+impl InputFormatter for EmbeddingFormatter {
+    fn format(&self, input: Value, _ctx: &mut FormatContext) -> Result<Value, AiError> {
+        let text = input.get("text")
+            .ok_or_else(|| AiError::InvalidInput("Missing 'text' field".to_string()))?;
+
+        let mut formatted = json!({ "text": text });
+        if let Some(pooling) = input.get("pooling") {
+            // Curated models reject bad values via their schema; this covers dynamic ones
+            if !matches!(pooling.as_str(), Some("mean" | "cls")) {
+                return Err(AiError::InvalidInput("'pooling' must be one of: \"mean\", \"cls\"".to_string()));
+            }
+            formatted["pooling"] = pooling.clone();
+        }
+        Ok(formatted)
+    }
+}
+
+/// Image generation models expect `{ prompt, num_steps?, width?, height? }`.
+struct ImageFormatter;
+
+impl InputFormatter for ImageFormatter {
+    fn format(&self, input: Value, ctx: &mut FormatContext) -> Result<Value, AiError> {
+        let prompt = input.get("prompt")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| AiError::InvalidInput("Missing 'prompt' field".to_string()))?;
+
+        let mut formatted = json!({ "prompt": prompt });
+        forward(&input, &mut formatted, &["width", "height"]);
+
+        if let Some(requested) = input.get("num_steps") {
+            let requested = requested.as_u64().ok_or_else(|| {
+                AiError::InvalidInput("'num_steps' must be a non-negative integer".to_string())
+            })?;
+            let (min, max) = ctx.model.step_range().unwrap_or((1, 20));
+            let applied = requested.clamp(min as u64, max as u64);
+
+            if applied != requested {
+                if ctx.options.strict {
+                    return Err(AiError::InvalidInput(format!(
+                        "'num_steps' must be between {} and {} for {}",
+                        min, max, ctx.model.id
+                    )));
+                }
+                ctx.meta.insert("num_steps_clamped".to_string(), json!({
+                    "requested": requested,
+                    "applied": applied,
+                }));
+            }
+
+            formatted["num_steps"] = json!(applied);
+        }
+
+        Ok(formatted)
+    }
+}
+
+/// Classifiers and detectors expect `{ image: [bytes] }`.
+struct ClassificationFormatter;
+
+impl InputFormatter for ClassificationFormatter {
+    fn format(&self, input: Value, _ctx: &mut FormatContext) -> Result<Value, AiError> {
+        let image = input.get("image")
+            .ok_or_else(|| AiError::InvalidInput("Missing 'image' field".to_string()))?;
+
+        Ok(json!({ "image": image }))
+    }
+}
+
+/// Vision-language models take the image plus a prompt about it.
+struct ImageToTextFormatter;
+
+impl InputFormatter for ImageToTextFormatter {
+    fn format(&self, input: Value, _ctx: &mut FormatContext) -> Result<Value, AiError> {
+        let image = input.get("image")
+            .ok_or_else(|| AiError::InvalidInput("Missing 'image' field".to_string()))?;
+
+        let mut formatted = json!({ "image": image });
+        forward(&input, &mut formatted, &["prompt", "max_tokens"]);
+        Ok(formatted)
+    }
+}
+
+/// Whisper expects `{ audio: [...] }`; `language` falls back to the deployment default.
+struct WhisperFormatter;
+
+impl InputFormatter for WhisperFormatter {
+    fn format(&self, mut input: Value, ctx: &mut FormatContext) -> Result<Value, AiError> {
+        if let (Some(language), Some(obj)) = (&ctx.options.audio_language, input.as_object_mut()) {
+            obj.entry("language").or_insert_with(|| json!(language));
+        }
+        Ok(input)
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use worker::*;
+use crate::ai::AiError;
+use crate::ai::bridge::js_error;
+use crate::config;
+use std::net::IpAddr;
+use base64::Engine;
+use futures_util::StreamExt;
+use base64::engine::general_purpose::STANDARD;
+use js_sys::{Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+/// Content types accepted from audio URLs unless `ALLOWED_AUDIO_TYPES` says otherwise.
+const DEFAULT_AUDIO_TYPES: &str = "audio/mpeg,audio/wav,audio/ogg";
+const MAX_REDIRECTS: usize = 5;
+
+/// What a URL fetch may return.
+struct FetchPolicy {
+    /// Exact content types, or `type/*` prefixes.
+    allowed_types: Vec<String>,
+    max_bytes: usize,
+}
+
+impl FetchPolicy {
+    fn image(env: &Env) -> Self {
+        Self {
+            allowed_types: vec!["image/*".to_string()],
+            max_bytes: config::max_body_bytes(env),
+        }
+    }
+
+    fn audio(env: &Env) -> Self {
+        let types = config::var(env, "ALLOWED_AUDIO_TYPES").unwrap_or_else(|| DEFAULT_AUDIO_TYPES.to_string());
+        Self {
+            allowed_types: types
+                .split(',')
+                .map(|t| t.trim().to_ascii_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            max_bytes: config::max_body_bytes(env),
+        }
+    }
+
+    fn allows(&self, content_type: &str) -> bool {
+        let content_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        self.allowed_types.iter().any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => content_type.starts_with(prefix),
+            None => content_type == *allowed,
+        })
+    }
+}
+
+/// Resolve an image argument into raw bytes. Accepts an `http(s)://` URL, a
+/// `data:` URL, a bare base64 string, or an array of byte values.
+pub async fn load_image(env: &Env, value: &serde_json::Value) -> std::result::Result<Vec<u8>, AiError> {
+    if let Some(bytes) = value.as_array() {
+        return bytes
+            .iter()
+            .map(|b| b.as_u64().filter(|b| *b <= 255).map(|b| b as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| AiError::InvalidInput("'image' byte array must contain values 0-255".to_string()));
+    }
+
+    let value = value
+        .as_str()
+        .ok_or_else(|| AiError::InvalidInput("'image' must be a URL, base64 string, or byte array".to_string()))?;
+
+    if is_http_url(value) {
+        return fetch_bytes(value, &FetchPolicy::image(env)).await;
+    }
+
+    decode_base64(value)
+}
+
+/// Fetch an `audio` argument given as an `http(s)://` URL. Other shapes are
+/// left for the model to interpret, so this returns `None` for them.
+pub async fn load_audio_url(env: &Env, value: &serde_json::Value) -> std::result::Result<Option<Vec<u8>>, AiError> {
+    match value.as_str() {
+        Some(url) if is_http_url(url) => fetch_bytes(url, &FetchPolicy::audio(env)).await.map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn is_http_url(value: &str) -> bool {
+    value.starts_with("http://") || value.starts_with("https://")
+}
+
+/// Decode base64, tolerating a `data:<mime>;base64,` prefix and embedded whitespace.
+pub fn decode_base64(value: &str) -> std::result::Result<Vec<u8>, AiError> {
+    let data = match value.strip_prefix("data:") {
+        Some(rest) => rest.split_once(',').map(|(_, data)| data).unwrap_or(rest),
+        None => value,
+    };
+    let cleaned: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+
+    STANDARD
+        .decode(cleaned)
+        .map_err(|e| AiError::InvalidInput(format!("Invalid base64 data: {}", e)))
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+/// Approximate decoded size of a base64 string without decoding it.
+pub fn base64_decoded_len(value: &str) -> usize {
+    value.len() / 4 * 3
+}
+
+/// Fetch a URL, following redirects by hand so every hop is checked against
+/// private address ranges, then enforce the policy's content types and size cap.
+async fn fetch_bytes(url: &str, policy: &FetchPolicy) -> std::result::Result<Vec<u8>, AiError> {
+    let mut url = Url::parse(url).map_err(|e| AiError::InvalidUrl(format!("Invalid URL: {}", e)))?;
+
+    let mut redirects = 0;
+    let mut response = loop {
+        check_public_host(&url)?;
+
+        let mut init = RequestInit::new();
+        init.with_redirect(RequestRedirect::Manual);
+        let request = Request::new_with_init(url.as_str(), &init).map_err(|e| fetch_failed(&url, e))?;
+        let response = Fetch::Request(request).send().await.map_err(|e| fetch_failed(&url, e))?;
+
+        if !(300..400).contains(&response.status_code()) {
+            break response;
+        }
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(AiError::InvalidUrl(format!("Too many redirects fetching {}", url)));
+        }
+        let location = response.headers().get("Location")?.ok_or_else(|| {
+            AiError::InvalidUrl(format!("Redirect from {} has no Location", url))
+        })?;
+        url = url.join(&location).map_err(|e| AiError::InvalidUrl(format!("Invalid redirect URL: {}", e)))?;
+    };
+
+    if response.status_code() != 200 {
+        return Err(AiError::InvalidUrl(format!(
+            "Fetching {} returned HTTP {}",
+            url,
+            response.status_code()
+        )));
+    }
+
+    let content_type = response.headers().get("Content-Type")?.unwrap_or_default();
+    if !policy.allows(&content_type) {
+        return Err(AiError::InvalidUrl(format!(
+            "{} has content type '{}'; allowed: {}",
+            url,
+            content_type,
+            policy.allowed_types.join(", ")
+        )));
+    }
+
+    let declared = response.headers().get("Content-Length")?.and_then(|l| l.parse::<usize>().ok());
+    if declared.is_some_and(|l| l > policy.max_bytes) {
+        return Err(too_large(&url, policy.max_bytes));
+    }
+
+    // Content-Length can be missing or wrong, so the body is counted as it
+    // arrives and dropped as soon as it goes over the cap
+    let mut body = response.stream().map_err(|e| fetch_failed(&url, e))?;
+    let mut bytes = Vec::with_capacity(declared.unwrap_or(0));
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| fetch_failed(&url, e))?;
+        if bytes.len() + chunk.len() > policy.max_bytes {
+            return Err(too_large(&url, policy.max_bytes));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+fn fetch_failed(url: &Url, e: Error) -> AiError {
+    AiError::InvalidUrl(format!("Fetching {} failed: {}", url, e))
+}
+
+fn too_large(url: &Url, max_bytes: usize) -> AiError {
+    AiError::InvalidUrl(format!("{} is larger than the {} byte limit", url, max_bytes))
+}
+
+/// Reject URLs pointing at loopback, private, or link-local addresses. Hostnames
+/// are only checked by name, since the resolved address isn't visible here: a
+/// public name that resolves to a private address gets through.
+fn check_public_host(url: &Url) -> std::result::Result<(), AiError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(AiError::InvalidUrl(format!("Only http(s) URLs can be fetched, not {}", url.scheme())));
+    }
+
+    let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    let blocked = match host.parse::<IpAddr>() {
+        Ok(ip) => is_private_ip(ip),
+        Err(_) => {
+            let host = host.to_ascii_lowercase();
+            host.is_empty()
+                || host == "localhost"
+                || host.ends_with(".localhost")
+                || host.ends_with(".local")
+                || host.ends_with(".internal")
+        }
+    };
+
+    if blocked {
+        return Err(AiError::InvalidUrl(format!("Refusing to fetch from private host '{}'", host)));
+    }
+    Ok(())
+}
+
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_private_ip(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Lock a `ReadableStream` and return its reader.
+pub fn stream_reader(body: &JsValue) -> Result<JsValue> {
+    let get_reader = Reflect::get(body, &JsValue::from_str("getReader"))
+        .map_err(|_| Error::RustError("AI stream has no getReader".to_string()))?
+        .dyn_into::<Function>()
+        .map_err(|_| Error::RustError("AI result is not a ReadableStream".to_string()))?;
+
+    get_reader.call0(body)
+        .map_err(|e| js_error("Failed to lock AI stream", e))
+}
+
+/// Read the next chunk from a stream reader, or `None` once it's done.
+pub async fn read_chunk(reader: &JsValue) -> Result<Option<Vec<u8>>> {
+    let read = Reflect::get(reader, &JsValue::from_str("read"))
+        .map_err(|_| Error::RustError("Failed to get read method".to_string()))?
+        .dyn_into::<Function>()
+        .map_err(|_| Error::RustError("read is not a function".to_string()))?;
+
+    let promise = read.call0(reader)
+        .map_err(|e| js_error("Failed to read AI stream", e))?
+        .dyn_into::<Promise>()
+        .map_err(|_| Error::RustError("read did not return a promise".to_string()))?;
+
+    let result = wasm_bindgen_futures::JsFuture::from(promise).await
+        .map_err(|e| js_error("AI stream failed", e))?;
+
+    let done = Reflect::get(&result, &JsValue::from_str("done"))
+        .ok()
+        .and_then(|d| d.as_bool())
+        .unwrap_or(true);
+    if done {
+        return Ok(None);
+    }
+
+    let value = Reflect::get(&result, &JsValue::from_str("value"))
+        .map_err(|_| Error::RustError("AI stream chunk has no value".to_string()))?;
+    Ok(Some(Uint8Array::new(&value).to_vec()))
+}
+
+/// Whether a value returned by `AI.run` is a `ReadableStream` rather than JSON.
+pub fn is_stream(value: &JsValue) -> bool {
+    Reflect::get(value, &JsValue::from_str("getReader")).is_ok_and(|f| f.is_function())
+}
+
+/// The bytes of an `ArrayBuffer` or typed array returned by `AI.run`, which
+/// don't map onto JSON. `None` for anything else.
+pub fn binary_bytes(value: &JsValue) -> Option<Vec<u8>> {
+    if value.is_instance_of::<js_sys::ArrayBuffer>() {
+        return Some(Uint8Array::new(value).to_vec());
+    }
+    if !js_sys::ArrayBuffer::is_view(value) {
+        return None;
+    }
+
+    // A view may cover only part of its buffer
+    let field = |name: &str| Reflect::get(value, &JsValue::from_str(name)).ok();
+    let buffer = field("buffer")?;
+    let offset = field("byteOffset")?.as_f64()? as u32;
+    let length = field("byteLength")?.as_f64()? as u32;
+    Some(Uint8Array::new_with_byte_offset_and_length(&buffer, offset, length).to_vec())
+}
+
+/// Drain a `ReadableStream` into memory.
+pub async fn read_all(body: &JsValue) -> Result<Vec<u8>> {
+    let reader = stream_reader(body)?;
+    let mut bytes = Vec::new();
+    while let Some(chunk) = read_chunk(&reader).await? {
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(url: &str) -> std::result::Result<(), AiError> {
+        check_public_host(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn public_hosts_are_allowed() {
+        assert!(check("https://example.com/cat.png").is_ok());
+        assert!(check("http://93.184.216.34/a.wav").is_ok());
+        assert!(check("https://[2606:4700::1111]/x").is_ok());
+    }
+
+    #[test]
+    fn private_hosts_are_refused_as_bad_urls() {
+        for url in [
+            "http://localhost/x",
+            "http://api.localhost/x",
+            "http://printer.local/x",
+            "http://127.0.0.1/x",
+            "http://10.1.2.3/x",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/x",
+            "http://[::1]/x",
+            "http://[::ffff:192.168.0.1]/x",
+        ] {
+            assert!(matches!(check(url), Err(AiError::InvalidUrl(_))), "{}", url);
+        }
+    }
+
+    #[test]
+    fn only_http_schemes_are_fetched() {
+        assert!(matches!(check("file:///etc/passwd"), Err(AiError::InvalidUrl(_))));
+        assert!(matches!(check("ftp://example.com/x"), Err(AiError::InvalidUrl(_))));
+    }
+}
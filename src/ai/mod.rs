@@ -4,7 +4,13 @@
 pub mod models;
 pub mod types;
 pub mod bridge;
+pub mod breaker;
+pub mod media;
+pub mod formatters;
+pub mod artifacts;
+pub mod sanitize;
+pub mod stats;
 
 pub use models::ModelRegistry;
-pub use types::AiResponse;
+pub use types::{AiError, AiResponse, AiStream, TokenUsage};
 pub use bridge::AiBridge;
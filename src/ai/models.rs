@@ -3,6 +3,11 @@
 
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use worker::{console_warn, Env};
+use crate::config;
+
+/// Width and height that image models' `base_neurons` are quoted at.
+const IMAGE_REFERENCE_SIDE: u64 = 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelInfo {
@@ -11,10 +16,15 @@ pub struct ModelInfo {
     pub description: String,
     pub category: ModelCategory,
     pub base_neurons: u32,
+    /// Most inputs the backend accepts in one call, for models that take a batch
+    /// (embeddings). Larger batches are split into calls of this size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch: Option<usize>,
     pub input_schema: serde_json::Value,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// Ordered as declared, which is the order models are listed in.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ModelCategory {
     #[serde(rename = "llm")]
     Llm,
@@ -24,33 +34,306 @@ pub enum ModelCategory {
     Image,
     #[serde(rename = "audio")]
     Audio,
+    #[serde(rename = "classification")]
+    Classification,
+    /// Vision-language models that answer a prompt about an image.
+    #[serde(rename = "image_to_text")]
+    ImageToText,
+}
+
+impl ModelCategory {
+    pub const ALL: [ModelCategory; 6] = [
+        ModelCategory::Llm,
+        ModelCategory::Embedding,
+        ModelCategory::Image,
+        ModelCategory::Audio,
+        ModelCategory::Classification,
+        ModelCategory::ImageToText,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelCategory::Llm => "llm",
+            ModelCategory::Embedding => "embedding",
+            ModelCategory::Image => "image",
+            ModelCategory::Audio => "audio",
+            ModelCategory::Classification => "classification",
+            ModelCategory::ImageToText => "image_to_text",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "llm" => Some(ModelCategory::Llm),
+            "embedding" => Some(ModelCategory::Embedding),
+            "image" => Some(ModelCategory::Image),
+            "audio" => Some(ModelCategory::Audio),
+            "classification" => Some(ModelCategory::Classification),
+            "image_to_text" => Some(ModelCategory::ImageToText),
+            _ => None,
+        }
+    }
+
+    /// Env var that overrides the default model for this category.
+    fn default_var(&self) -> &'static str {
+        match self {
+            ModelCategory::Llm => "DEFAULT_LLM",
+            ModelCategory::Embedding => "DEFAULT_EMBEDDING",
+            ModelCategory::Image => "DEFAULT_IMAGE",
+            ModelCategory::Audio => "DEFAULT_AUDIO",
+            ModelCategory::Classification => "DEFAULT_CLASSIFICATION",
+            ModelCategory::ImageToText => "DEFAULT_IMAGE_TO_TEXT",
+        }
+    }
+
+    fn builtin_default(&self) -> &'static str {
+        match self {
+            ModelCategory::Llm => "@cf/meta/llama-3.1-8b-instruct",
+            ModelCategory::Embedding => "@cf/baai/bge-base-en-v1.5",
+            ModelCategory::Image => "@cf/stabilityai/stable-diffusion-xl-base-1.0",
+            ModelCategory::Audio => "@cf/openai/whisper",
+            ModelCategory::Classification => "@cf/microsoft/resnet-50",
+            ModelCategory::ImageToText => "@cf/llava-hf/llava-1.5-7b-hf",
+        }
+    }
+}
+
+/// How an estimate was reached: the token counts it used (LLMs and embeddings)
+/// and the category-specific inputs to its formula.
+#[derive(Debug, Clone, Serialize)]
+pub struct CostBreakdown {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u32>,
+    pub factors: serde_json::Value,
+    pub neurons: u32,
 }
 
 impl ModelInfo {
     pub fn estimate_neurons(&self, input: &serde_json::Value) -> u32 {
+        self.cost_breakdown(input).neurons
+    }
+
+    /// The estimate `estimate_neurons` gives, with the figures behind it: the
+    /// prompt and completion token estimates and the formula's other inputs.
+    pub fn cost_breakdown(&self, input: &serde_json::Value) -> CostBreakdown {
         match self.category {
             ModelCategory::Llm => {
-                let prompt = input.get("prompt")
-                    .and_then(|p| p.as_str())
-                    .unwrap_or("");
-                let tokens = (prompt.len() / 4).max(1) as u32;
-                tokens + 100
+                let tokens = Self::estimate_prompt_tokens(input);
+                let max_tokens = input.get("max_tokens")
+                    .and_then(|m| m.as_u64())
+                    .map(|m| m.min(u32::MAX as u64) as u32)
+                    .unwrap_or_else(|| self.default_max_tokens());
+                CostBreakdown {
+                    input_tokens: Some(tokens),
+                    output_tokens: Some(max_tokens),
+                    factors: json!({ "overhead": 100 }),
+                    neurons: tokens.saturating_add(max_tokens).saturating_add(100),
+                }
             }
             ModelCategory::Embedding => {
                 let text = input.get("text")
                     .and_then(|t| t.as_str())
                     .unwrap_or("");
                 let tokens = (text.len() / 4).max(1) as u32;
-                tokens / 10
+                CostBreakdown {
+                    input_tokens: Some(tokens),
+                    output_tokens: None,
+                    factors: json!({ "tokens_per_neuron": 10 }),
+                    neurons: tokens / 10,
+                }
+            }
+            ModelCategory::Image => {
+                // `base_neurons` is the cost at the default step count and the
+                // reference resolution; scale linearly with both
+                let default_steps = self.default_steps().max(1) as u64;
+                let steps = self.effective_steps(input).map_or(default_steps, u64::from);
+                let side = |key: &str| input.get(key)
+                    .and_then(|v| v.as_u64())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(IMAGE_REFERENCE_SIDE);
+                let (width, height) = (side("width"), side("height"));
+
+                let estimate = (self.base_neurons as u64 * steps / default_steps)
+                    .saturating_mul(width.saturating_mul(height)) / (IMAGE_REFERENCE_SIDE * IMAGE_REFERENCE_SIDE);
+                CostBreakdown {
+                    input_tokens: None,
+                    output_tokens: None,
+                    factors: json!({
+                        "base_neurons": self.base_neurons,
+                        "steps": steps,
+                        "default_steps": default_steps,
+                        "width": width,
+                        "height": height,
+                        "reference_side": IMAGE_REFERENCE_SIDE,
+                    }),
+                    neurons: estimate.clamp(1, u32::MAX as u64) as u32,
+                }
             }
-            ModelCategory::Image => 5000,
             ModelCategory::Audio => {
-                input.get("audio")
-                    .and_then(|a| a.as_str())
-                    .map(|s| (s.len() / 1000).max(1) as u32 * 10)
-                    .unwrap_or(100)
+                let audio_chars = input.get("audio").and_then(|a| a.as_str()).map(str::len);
+                CostBreakdown {
+                    input_tokens: None,
+                    output_tokens: None,
+                    factors: json!({ "audio_chars": audio_chars, "neurons_per_1000_chars": 10 }),
+                    neurons: audio_chars.map(|len| (len / 1000).max(1) as u32 * 10).unwrap_or(100),
+                }
+            }
+            ModelCategory::Classification => {
+                // Scale with the decoded image size, falling back to the base cost
+                let bytes = Self::image_bytes(input);
+                CostBreakdown {
+                    input_tokens: None,
+                    output_tokens: None,
+                    factors: json!({
+                        "base_neurons": self.base_neurons,
+                        "image_bytes": bytes,
+                        "bytes_per_neuron": 100_000,
+                    }),
+                    neurons: self.base_neurons + (bytes / 100_000) as u32,
+                }
+            }
+            ModelCategory::ImageToText => {
+                // The image costs what it would for a classifier, plus the text generated
+                let bytes = Self::image_bytes(input);
+                let tokens = Self::estimate_prompt_tokens(input);
+                let max_tokens = input.get("max_tokens")
+                    .and_then(|m| m.as_u64())
+                    .map(|m| m.min(u32::MAX as u64) as u32)
+                    .unwrap_or_else(|| self.default_max_tokens());
+                CostBreakdown {
+                    input_tokens: Some(tokens),
+                    output_tokens: Some(max_tokens),
+                    factors: json!({
+                        "base_neurons": self.base_neurons,
+                        "image_bytes": bytes,
+                        "bytes_per_neuron": 100_000,
+                    }),
+                    neurons: (self.base_neurons + (bytes / 100_000) as u32)
+                        .saturating_add(tokens)
+                        .saturating_add(max_tokens),
+                }
+            }
+        }
+    }
+
+    /// Size of an `image` argument as far as it's visible before fetching or
+    /// decoding: decoded base64 or byte array length, 0 for URLs.
+    fn image_bytes(input: &serde_json::Value) -> usize {
+        match input.get("image") {
+            Some(serde_json::Value::String(s)) if !s.starts_with("http") => {
+                crate::ai::media::base64_decoded_len(s)
+            }
+            Some(serde_json::Value::Array(a)) => a.len(),
+            _ => 0,
+        }
+    }
+
+    /// Only text generation models can answer with incremental SSE output.
+    pub fn supports_streaming(&self) -> bool {
+        self.category == ModelCategory::Llm
+    }
+
+    /// Rough input token count (~4 characters per token) from `prompt`,
+    /// `messages[].content`, or `text` (a string or array of strings).
+    pub fn estimate_prompt_tokens(input: &serde_json::Value) -> u32 {
+        let len: usize = if let Some(messages) = input.get("messages").and_then(|m| m.as_array()) {
+            messages.iter()
+                .filter_map(|m| m.get("content").and_then(|c| c.as_str()))
+                .map(str::len)
+                .sum()
+        } else if let Some(prompt) = input.get("prompt").and_then(|p| p.as_str()) {
+            prompt.len()
+        } else {
+            match input.get("text") {
+                Some(serde_json::Value::String(text)) => text.len(),
+                Some(serde_json::Value::Array(texts)) => texts.iter()
+                    .filter_map(|t| t.as_str())
+                    .map(str::len)
+                    .sum(),
+                _ => 0,
+            }
+        };
+        (len / 4).max(1) as u32
+    }
+
+    /// The schema default for `max_tokens`, falling back to 256.
+    pub fn default_max_tokens(&self) -> u32 {
+        self.input_schema
+            .pointer("/properties/max_tokens/default")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32)
+            .unwrap_or(256)
+    }
+
+    /// Supported `num_steps` range for image models, `None` for everything else.
+    pub fn step_range(&self) -> Option<(u32, u32)> {
+        if self.category != ModelCategory::Image {
+            return None;
+        }
+        if self.id.contains("flux-1-schnell") {
+            Some((1, 8))
+        } else {
+            Some((1, 20))
+        }
+    }
+
+    /// The schema default for `num_steps`, or the top of the supported range.
+    pub fn default_steps(&self) -> u32 {
+        self.input_schema
+            .pointer("/properties/num_steps/default")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32)
+            .or_else(|| self.step_range().map(|(_, max)| max))
+            .unwrap_or(1)
+    }
+
+    /// The `num_steps` that will actually be sent: the requested value clamped to
+    /// the supported range, or `None` when the caller didn't ask for one.
+    pub fn effective_steps(&self, input: &serde_json::Value) -> Option<u32> {
+        let (min, max) = self.step_range()?;
+        let requested = input.get("num_steps")?.as_u64()?;
+        Some(requested.clamp(min as u64, max as u64) as u32)
+    }
+}
+
+/// Tool name that runs the `LENGTH_ROUTES` rule instead of a fixed model.
+pub const LENGTH_ROUTE_ALIAS: &str = "auto";
+
+/// `LENGTH_ROUTES`: send prompts estimated below `threshold` tokens to `under`
+/// and the rest to `over`, e.g. a small model for short questions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LengthRoute {
+    pub under: String,
+    pub over: String,
+    pub threshold: u32,
+}
+
+impl LengthRoute {
+    /// The configured route, if both of its models are usable LLMs. Anything
+    /// else is ignored with a warning.
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let raw = config::var(env, "LENGTH_ROUTES")?;
+        let route: LengthRoute = serde_json::from_str(&raw)
+            .map_err(|e| console_warn!("Ignoring LENGTH_ROUTES: {}", e))
+            .ok()?;
+
+        for id in [&route.under, &route.over] {
+            let usable = ModelRegistry::lookup(env, id).is_some_and(|m| m.category == ModelCategory::Llm);
+            if !usable {
+                console_warn!("Ignoring LENGTH_ROUTES: {} is not a usable LLM", id);
+                return None;
             }
         }
+        Some(route)
+    }
+
+    /// The model for `input` and the token estimate it was picked by.
+    pub fn pick(&self, input: &serde_json::Value) -> (&str, u32) {
+        let tokens = ModelInfo::estimate_prompt_tokens(input);
+        let model = if tokens < self.threshold { &self.under } else { &self.over };
+        (model, tokens)
     }
 }
 
@@ -65,6 +348,7 @@ impl ModelRegistry {
                 description: "Meta's Llama 3.1 8B instruction-tuned model for text generation".to_string(),
                 category: ModelCategory::Llm,
                 base_neurons: 100,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -87,6 +371,7 @@ impl ModelRegistry {
                 description: "Mistral's 7B instruction-tuned model for text generation".to_string(),
                 category: ModelCategory::Llm,
                 base_neurons: 90,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -109,12 +394,18 @@ impl ModelRegistry {
                 description: "BAAI's text embedding model for semantic search and similarity".to_string(),
                 category: ModelCategory::Embedding,
                 base_neurons: 10,
+                max_batch: Some(100),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
                         "text": {
                             "type": "string",
                             "description": "The text to generate embeddings for"
+                        },
+                        "pooling": {
+                            "type": "string",
+                            "enum": ["mean", "cls"],
+                            "description": "How token embeddings are pooled; 'cls' suits retrieval with long inputs. Defaults to the model's own (mean)"
                         }
                     },
                     "required": ["text"]
@@ -126,6 +417,7 @@ impl ModelRegistry {
                 description: "Stability AI's SDXL model for high-quality image generation".to_string(),
                 category: ModelCategory::Image,
                 base_neurons: 5000,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -148,6 +440,7 @@ impl ModelRegistry {
                 description: "OpenAI's Whisper model for speech recognition and transcription".to_string(),
                 category: ModelCategory::Audio,
                 base_neurons: 100,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -170,6 +463,7 @@ impl ModelRegistry {
                 description: "Meta's Llama 3.1 70B large-scale multilingual instruction model".to_string(),
                 category: ModelCategory::Llm,
                 base_neurons: 300,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -185,6 +479,7 @@ impl ModelRegistry {
                 description: "Meta's Llama 3.2 1B small multilingual dialogue model".to_string(),
                 category: ModelCategory::Llm,
                 base_neurons: 50,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -200,6 +495,7 @@ impl ModelRegistry {
                 description: "Qwen's code-specific model for programming tasks".to_string(),
                 category: ModelCategory::Llm,
                 base_neurons: 200,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -216,10 +512,12 @@ impl ModelRegistry {
                 description: "BAAI's large 1024-dimensional English embeddings".to_string(),
                 category: ModelCategory::Embedding,
                 base_neurons: 15,
+                max_batch: Some(100),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
-                        "text": { "type": "string", "description": "Text to embed" }
+                        "text": { "type": "string", "description": "Text to embed" },
+                        "pooling": { "type": "string", "enum": ["mean", "cls"], "description": "Token pooling; defaults to mean" }
                     },
                     "required": ["text"]
                 }),
@@ -230,6 +528,7 @@ impl ModelRegistry {
                 description: "BAAI's multi-functional, multilingual, multi-granular embeddings".to_string(),
                 category: ModelCategory::Embedding,
                 base_neurons: 20,
+                max_batch: Some(100),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -245,6 +544,7 @@ impl ModelRegistry {
                 description: "Black Forest Labs' fast 12B parameter image generation model".to_string(),
                 category: ModelCategory::Image,
                 base_neurons: 4000,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -260,6 +560,7 @@ impl ModelRegistry {
                 description: "ByteDance's high-quality 1024px image generation in few steps".to_string(),
                 category: ModelCategory::Image,
                 base_neurons: 3500,
+                max_batch: None,
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -269,9 +570,64 @@ impl ModelRegistry {
                     "required": ["prompt"]
                 }),
             },
+            // Image classification / object detection models
+            ModelInfo {
+                id: "@cf/microsoft/resnet-50".to_string(),
+                name: "ResNet-50".to_string(),
+                description: "Microsoft's image classifier returning class labels with confidence scores".to_string(),
+                category: ModelCategory::Classification,
+                base_neurons: 20,
+                max_batch: None,
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "image": { "type": "string", "description": "Image as a URL or base64-encoded data" }
+                    },
+                    "required": ["image"]
+                }),
+            },
+            ModelInfo {
+                id: "@cf/facebook/detr-resnet-50".to_string(),
+                name: "DETR ResNet-50".to_string(),
+                description: "Facebook's object detection model returning labeled bounding boxes with scores".to_string(),
+                category: ModelCategory::Classification,
+                base_neurons: 50,
+                max_batch: None,
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "image": { "type": "string", "description": "Image as a URL or base64-encoded data" }
+                    },
+                    "required": ["image"]
+                }),
+            },
+            // Image-to-text models
+            ModelInfo {
+                id: "@cf/llava-hf/llava-1.5-7b-hf".to_string(),
+                name: "LLaVA 1.5 7B".to_string(),
+                description: "Vision-language model that answers a prompt about an image".to_string(),
+                category: ModelCategory::ImageToText,
+                base_neurons: 60,
+                max_batch: None,
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "image": { "type": "string", "description": "Image as a URL or base64-encoded data" },
+                        "prompt": { "type": "string", "description": "Question or instruction about the image" },
+                        "max_tokens": { "type": "integer", "description": "Maximum tokens to generate", "default": 256 }
+                    },
+                    "required": ["image"]
+                }),
+            },
         ]
     }
 
+    /// Put models in listing order: by category, then by id. Clients get the
+    /// same order from every request and deployment whatever the source order.
+    pub fn sort_for_listing(models: &mut [ModelInfo]) {
+        models.sort_by(|a, b| a.category.cmp(&b.category).then_with(|| a.id.cmp(&b.id)));
+    }
+
     pub fn get_model(id: &str) -> Option<ModelInfo> {
         // First check if it's in our curated list
         if let Some(model) = Self::get_all_models().into_iter().find(|m| m.id == id) {
@@ -282,9 +638,187 @@ impl ModelRegistry {
         Self::create_dynamic_model(id)
     }
 
+    /// Like `get_model`, but a dynamic model takes its category from `hint`
+    /// instead of the substring heuristic. Curated models ignore the hint.
+    pub fn get_model_with_hint(id: &str, hint: Option<ModelCategory>) -> Option<ModelInfo> {
+        match hint {
+            Some(category) if !Self::is_curated(id) && Self::is_well_formed_id(id) => {
+                Some(Self::dynamic_model(id, category))
+            }
+            _ => Self::get_model(id),
+        }
+    }
+
+    /// Model info for any id at all, for `PERMISSIVE_MODELS` deployments that
+    /// let the backend decide whether an odd-looking id exists.
+    pub fn permissive_model(id: &str, hint: Option<ModelCategory>) -> ModelInfo {
+        Self::get_model_with_hint(id, hint.clone())
+            .unwrap_or_else(|| Self::dynamic_model(id, hint.unwrap_or_else(|| Self::infer_category(id))))
+    }
+
+    /// The model `id` names on this deployment: a curated one, or with
+    /// `DYNAMIC_MODELS` on, one inferred from a well-formed id, with any
+    /// `SCHEMA_OVERRIDES` entry merged into its input schema. Schema lookups,
+    /// `model://` resources and configured routes all go through this, so they
+    /// accept exactly the ids `tools/call` does and show the schema it enforces.
+    pub fn lookup(env: &Env, id: &str) -> Option<ModelInfo> {
+        let mut model = Self::get_model(id).filter(|m| Self::is_curated(&m.id) || Self::dynamic_models(env))?;
+        if let Some(patch) = Self::schema_overrides(env).get(id) {
+            merge_schema(&mut model.input_schema, patch);
+        }
+        Some(model)
+    }
+
+    /// The model to use for `category` when the caller didn't name one: the
+    /// `DEFAULT_<CATEGORY>` env var if it names a usable model of that category,
+    /// else a built-in.
+    pub fn default_for(env: &Env, category: &ModelCategory) -> String {
+        let var = category.default_var();
+        let builtin = category.builtin_default();
+        let Some(id) = config::var(env, var) else {
+            return builtin.to_string();
+        };
+
+        match Self::lookup(env, &id) {
+            Some(model) if model.category == *category => id,
+            Some(model) => {
+                console_warn!(
+                    "{} is set to '{}', which is a {} model, not {}; using {}",
+                    var, id, model.category.as_str(), category.as_str(), builtin
+                );
+                builtin.to_string()
+            }
+            None => {
+                console_warn!("{} is set to unknown model '{}', using {}", var, id, builtin);
+                builtin.to_string()
+            }
+        }
+    }
+
+    /// The curated models with `SCHEMA_OVERRIDES` applied to their input schemas.
+    pub fn get_all_models_for(env: &Env) -> Vec<ModelInfo> {
+        let overrides = Self::schema_overrides(env);
+        let mut models = Self::get_all_models();
+        for model in &mut models {
+            if let Some(patch) = overrides.get(&model.id) {
+                merge_schema(&mut model.input_schema, patch);
+            }
+        }
+        models
+    }
+
+    /// A model's input schema with any `SCHEMA_OVERRIDES` entry merged over it.
+    pub fn input_schema_for(env: &Env, id: &str) -> Option<serde_json::Value> {
+        Self::lookup(env, id).map(|model| model.input_schema)
+    }
+
+    /// `SCHEMA_OVERRIDES`: a JSON object mapping model ids to partial schemas.
+    /// A malformed variable, or an entry that isn't an object, is skipped with a warning.
+    fn schema_overrides(env: &Env) -> serde_json::Map<String, serde_json::Value> {
+        let Some(raw) = config::var(env, "SCHEMA_OVERRIDES") else {
+            return serde_json::Map::new();
+        };
+        let overrides = match serde_json::from_str::<serde_json::Value>(&raw) {
+            Ok(serde_json::Value::Object(overrides)) => overrides,
+            Ok(_) => {
+                console_warn!("Ignoring SCHEMA_OVERRIDES: expected a JSON object of model ids");
+                return serde_json::Map::new();
+            }
+            Err(e) => {
+                console_warn!("Ignoring SCHEMA_OVERRIDES: {}", e);
+                return serde_json::Map::new();
+            }
+        };
+
+        overrides
+            .into_iter()
+            .filter(|(id, patch)| {
+                let ok = patch.is_object();
+                if !ok {
+                    console_warn!("Ignoring SCHEMA_OVERRIDES entry for {}: not an object", id);
+                }
+                ok
+            })
+            .collect()
+    }
+
+    /// The models to try, in order, when `model` keeps failing (`FALLBACK_CHAIN`).
+    /// The variable is either a comma-separated list used for every model, or a
+    /// JSON object mapping model ids to lists, with `"*"` for the rest. Entries
+    /// that aren't usable models of the same category are dropped, as are repeats
+    /// and `model` itself, so a chain can't loop.
+    pub fn fallback_chain(env: &Env, model: &ModelInfo) -> Vec<String> {
+        match config::var(env, "FALLBACK_CHAIN") {
+            Some(raw) => Self::parse_fallback_chain(&raw, model, |id| Self::lookup(env, id)),
+            None => Vec::new(),
+        }
+    }
+
+    /// `fallback_chain` for the raw `FALLBACK_CHAIN` value, with `lookup` deciding
+    /// which ids are usable models.
+    fn parse_fallback_chain(raw: &str, model: &ModelInfo, lookup: impl Fn(&str) -> Option<ModelInfo>) -> Vec<String> {
+        let per_model = raw.trim_start().starts_with('{');
+        let entries: Vec<String> = if per_model {
+            match serde_json::from_str::<std::collections::HashMap<String, Vec<String>>>(raw) {
+                Ok(mut chains) => chains.remove(&model.id).or_else(|| chains.remove("*")).unwrap_or_default(),
+                Err(e) => {
+                    console_warn!("Ignoring FALLBACK_CHAIN: {}", e);
+                    return Vec::new();
+                }
+            }
+        } else {
+            raw.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect()
+        };
+
+        let mut chain: Vec<String> = Vec::new();
+        for id in entries {
+            if id == model.id || chain.contains(&id) {
+                continue;
+            }
+            match lookup(&id) {
+                Some(fallback) if fallback.category == model.category => chain.push(id),
+                // A global list spans categories; each model only uses its own kind
+                Some(_) if !per_model => {}
+                _ => console_warn!("Ignoring FALLBACK_CHAIN entry {} for {}: not a usable {} model", id, model.id, model.category.as_str()),
+            }
+        }
+        chain
+    }
+
+    pub fn is_curated(id: &str) -> bool {
+        Self::get_all_models().iter().any(|m| m.id == id)
+    }
+
+    /// `DYNAMIC_MODELS` (default on): whether ids outside the curated list may be
+    /// used at all. Off, only vetted models run and typos are unknown models.
+    pub fn dynamic_models(env: &Env) -> bool {
+        config::flag(env, "DYNAMIC_MODELS", true)
+    }
+
+    /// Whether `id` looks like a Workers AI model: `@cf/<vendor>/<name>` or `@hf/<vendor>/<name>`.
+    fn is_well_formed_id(id: &str) -> bool {
+        let Some(rest) = id.strip_prefix("@cf/").or_else(|| id.strip_prefix("@hf/")) else {
+            return false;
+        };
+        let mut parts = rest.split('/');
+        let valid = |part: Option<&str>| part.is_some_and(|p| !p.is_empty() && !p.chars().any(char::is_whitespace));
+        valid(parts.next()) && valid(parts.next()) && parts.next().is_none()
+    }
+
     fn create_dynamic_model(id: &str) -> Option<ModelInfo> {
         // For models not in our curated list, infer category from ID
-        let (category, base_neurons, input_schema) = if id.contains("llama")
+        if !Self::is_well_formed_id(id) {
+            return None;
+        }
+        Some(Self::dynamic_model(id, Self::infer_category(id)))
+    }
+
+    fn infer_category(id: &str) -> ModelCategory {
+        // Vision-language ids often name their language model too (uform-gen2-qwen)
+        if id.contains("llava")
+            || id.contains("uform") {
+            ModelCategory::ImageToText
+        } else if id.contains("llama")
             || id.contains("mistral")
             || id.contains("qwen")
             || id.contains("gemma")
@@ -303,63 +837,275 @@ impl ModelRegistry {
             || id.contains("chat")
             || id.contains("instruct")
             || id.contains("granite") {
-            (ModelCategory::Llm, 100, json!({
+            ModelCategory::Llm
+        } else if id.contains("bge")
+            || id.contains("embedding")
+            || id.contains("embed") {
+            ModelCategory::Embedding
+        } else if id.contains("stable-diffusion")
+            || id.contains("flux")
+            || id.contains("dreamshaper")
+            || id.contains("lucid")
+            || id.contains("phoenix") {
+            ModelCategory::Image
+        } else if id.contains("whisper")
+            || id.contains("nova")
+            || id.contains("asr") {
+            ModelCategory::Audio
+        } else if id.contains("resnet")
+            || id.contains("detr") {
+            ModelCategory::Classification
+        } else {
+            // Unknown model - default to LLM
+            ModelCategory::Llm
+        }
+    }
+
+    fn dynamic_model(id: &str, category: ModelCategory) -> ModelInfo {
+        let (base_neurons, input_schema) = match category {
+            ModelCategory::Llm => (100, json!({
                 "type": "object",
                 "properties": {
                     "prompt": { "type": "string", "description": "Text prompt" }
                 },
                 "required": ["prompt"]
-            }))
-        } else if id.contains("bge")
-            || id.contains("embedding")
-            || id.contains("embed") {
-            (ModelCategory::Embedding, 10, json!({
+            })),
+            ModelCategory::Embedding => (10, json!({
                 "type": "object",
                 "properties": {
                     "text": { "type": "string", "description": "Text to embed" }
                 },
                 "required": ["text"]
-            }))
-        } else if id.contains("stable-diffusion")
-            || id.contains("flux")
-            || id.contains("dreamshaper")
-            || id.contains("lucid")
-            || id.contains("phoenix") {
-            (ModelCategory::Image, 5000, json!({
+            })),
+            ModelCategory::Image => (5000, json!({
                 "type": "object",
                 "properties": {
                     "prompt": { "type": "string", "description": "Image description" }
                 },
                 "required": ["prompt"]
-            }))
-        } else if id.contains("whisper")
-            || id.contains("nova")
-            || id.contains("asr") {
-            (ModelCategory::Audio, 100, json!({
+            })),
+            ModelCategory::Audio => (100, json!({
                 "type": "object",
                 "properties": {
                     "audio": { "type": "string", "description": "Base64 audio" }
                 },
                 "required": ["audio"]
-            }))
-        } else {
-            // Unknown model - default to LLM
-            (ModelCategory::Llm, 100, json!({
+            })),
+            ModelCategory::Classification => (20, json!({
                 "type": "object",
                 "properties": {
-                    "prompt": { "type": "string" }
+                    "image": { "type": "string", "description": "Image URL or base64" }
                 },
-                "required": ["prompt"]
-            }))
+                "required": ["image"]
+            })),
+            ModelCategory::ImageToText => (60, json!({
+                "type": "object",
+                "properties": {
+                    "image": { "type": "string", "description": "Image URL or base64" },
+                    "prompt": { "type": "string", "description": "Question or instruction about the image" }
+                },
+                "required": ["image"]
+            })),
         };
 
-        Some(ModelInfo {
+        let max_batch = (category == ModelCategory::Embedding).then_some(100);
+
+        ModelInfo {
             id: id.to_string(),
             name: id.split('/').last().unwrap_or(id).replace('-', " ").to_string(),
             description: format!("Auto-detected model: {}", id),
             category,
             base_neurons,
+            max_batch,
             input_schema,
-        })
+        }
+    }
+}
+
+/// Merge `patch` into `schema`: objects are merged key by key, anything else
+/// (including arrays such as `enum` and `required`) replaces what was there.
+fn merge_schema(schema: &mut serde_json::Value, patch: &serde_json::Value) {
+    match (schema, patch) {
+        (serde_json::Value::Object(schema), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                match schema.get_mut(key) {
+                    Some(existing) => merge_schema(existing, value),
+                    None => {
+                        schema.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (schema, patch) => *schema = patch.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(id: &str) -> ModelInfo {
+        ModelRegistry::get_model(id).unwrap()
+    }
+
+    #[test]
+    fn step_range_depends_on_the_image_model() {
+        assert_eq!(model("@cf/black-forest-labs/flux-1-schnell").step_range(), Some((1, 8)));
+        assert_eq!(model("@cf/stabilityai/stable-diffusion-xl-base-1.0").step_range(), Some((1, 20)));
+        assert_eq!(model("@cf/meta/llama-3.1-8b-instruct").step_range(), None);
+    }
+
+    #[test]
+    fn effective_steps_are_clamped() {
+        let flux = model("@cf/black-forest-labs/flux-1-schnell");
+        assert_eq!(flux.effective_steps(&json!({ "num_steps": 50 })), Some(8));
+        assert_eq!(flux.effective_steps(&json!({ "num_steps": 0 })), Some(1));
+        assert_eq!(flux.effective_steps(&json!({})), None);
+        assert_eq!(flux.default_steps(), 4);
+    }
+
+    #[test]
+    fn image_cost_scales_with_steps_and_resolution() {
+        let sdxl = model("@cf/stabilityai/stable-diffusion-xl-base-1.0");
+        let base = sdxl.base_neurons;
+        assert_eq!(sdxl.estimate_neurons(&json!({ "prompt": "cat" })), base);
+        assert_eq!(sdxl.estimate_neurons(&json!({ "prompt": "cat", "num_steps": 10 })), base / 2);
+        assert_eq!(sdxl.estimate_neurons(&json!({ "prompt": "cat", "width": 512, "height": 512 })), base / 4);
+        assert_eq!(sdxl.estimate_neurons(&json!({ "prompt": "cat", "width": 2048 })), base * 2);
+
+        let breakdown = sdxl.cost_breakdown(&json!({ "prompt": "cat", "num_steps": 10, "width": 512 }));
+        assert_eq!(breakdown.factors["steps"], json!(10));
+        assert_eq!(breakdown.factors["width"], json!(512));
+        assert_eq!(breakdown.factors["height"], json!(1024));
+        assert_eq!(breakdown.input_tokens, None);
+    }
+
+    #[test]
+    fn image_cost_never_drops_to_zero_or_overflows() {
+        let sdxl = model("@cf/stabilityai/stable-diffusion-xl-base-1.0");
+        assert_eq!(sdxl.estimate_neurons(&json!({ "width": 1, "height": 1, "num_steps": 1 })), 1);
+        assert_eq!(sdxl.estimate_neurons(&json!({ "width": u64::MAX, "height": u64::MAX })), u32::MAX);
+    }
+
+    #[test]
+    fn llm_cost_counts_prompt_and_completion_tokens() {
+        let llama = model("@cf/meta/llama-3.1-8b-instruct");
+        let breakdown = llama.cost_breakdown(&json!({ "prompt": "a".repeat(400), "max_tokens": 50 }));
+        assert_eq!(breakdown.input_tokens, Some(100));
+        assert_eq!(breakdown.output_tokens, Some(50));
+        assert_eq!(breakdown.neurons, 250);
+        assert_eq!(breakdown.neurons, llama.estimate_neurons(&json!({ "prompt": "a".repeat(400), "max_tokens": 50 })));
+
+        let defaulted = llama.cost_breakdown(&json!({ "messages": [{ "role": "user", "content": "hi" }] }));
+        assert_eq!(defaulted.input_tokens, Some(1));
+        assert_eq!(defaulted.output_tokens, Some(llama.default_max_tokens()));
+    }
+
+    #[test]
+    fn listing_order_is_category_then_id() {
+        let mut models = vec![
+            model("@cf/openai/whisper"),
+            model("@cf/meta/llama-3.2-1b-instruct"),
+            model("@cf/baai/bge-base-en-v1.5"),
+            model("@cf/meta/llama-3.1-8b-instruct"),
+        ];
+        ModelRegistry::sort_for_listing(&mut models);
+        let ids: Vec<&str> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec![
+            "@cf/meta/llama-3.1-8b-instruct",
+            "@cf/meta/llama-3.2-1b-instruct",
+            "@cf/baai/bge-base-en-v1.5",
+            "@cf/openai/whisper",
+        ]);
+    }
+
+    #[test]
+    fn sorted_curated_listing_is_in_order() {
+        let mut models = ModelRegistry::get_all_models();
+        ModelRegistry::sort_for_listing(&mut models);
+        let keys: Vec<(ModelCategory, String)> = models.iter().map(|m| (m.category.clone(), m.id.clone())).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn image_to_text_cost_adds_generated_text_to_the_image_cost() {
+        let llava = model("@cf/llava-hf/llava-1.5-7b-hf");
+        let input = json!({ "image": "A".repeat(400_000), "prompt": "a".repeat(40), "max_tokens": 100 });
+        let breakdown = llava.cost_breakdown(&input);
+        assert_eq!(breakdown.input_tokens, Some(10));
+        assert_eq!(breakdown.output_tokens, Some(100));
+        assert_eq!(breakdown.neurons, 60 + 3 + 10 + 100);
+    }
+
+    #[test]
+    fn builtin_defaults_belong_to_their_category() {
+        for category in ModelCategory::ALL {
+            let default = model(category.builtin_default());
+            assert!(ModelRegistry::is_curated(&default.id));
+            assert_eq!(default.category, category);
+        }
+    }
+
+    #[test]
+    fn vision_language_models_are_image_to_text() {
+        assert_eq!(model("@cf/llava-hf/llava-1.5-7b-hf").category, ModelCategory::ImageToText);
+        assert_eq!(ModelRegistry::get_model("@cf/unum/uform-gen2-qwen-500m").unwrap().category, ModelCategory::ImageToText);
+        assert_eq!(ModelRegistry::get_model("@cf/vendor/resnet-101").unwrap().category, ModelCategory::Classification);
+        for category in ModelCategory::ALL {
+            assert_eq!(ModelCategory::from_name(category.as_str()), Some(category.clone()));
+        }
+    }
+
+    fn chain(raw: &str, id: &str) -> Vec<String> {
+        ModelRegistry::parse_fallback_chain(raw, &model(id), |id| ModelRegistry::get_model(id).filter(|m| ModelRegistry::is_curated(&m.id)))
+    }
+
+    #[test]
+    fn global_fallback_chain_keeps_same_category_models_in_order() {
+        let raw = "@cf/meta/llama-3.1-70b-instruct, @cf/baai/bge-base-en-v1.5,@cf/meta/llama-3.2-1b-instruct,";
+        assert_eq!(
+            chain(raw, "@cf/meta/llama-3.1-8b-instruct"),
+            vec!["@cf/meta/llama-3.1-70b-instruct", "@cf/meta/llama-3.2-1b-instruct"]
+        );
+        assert_eq!(chain(raw, "@cf/baai/bge-large-en-v1.5"), vec!["@cf/baai/bge-base-en-v1.5"]);
+    }
+
+    #[test]
+    fn fallback_chain_drops_repeats_self_and_unknown_models() {
+        let raw = "@cf/meta/llama-3.1-8b-instruct,@cf/meta/llama-3.2-1b-instruct,@cf/meta/llama-3.2-1b-instruct,@cf/vendor/unlisted";
+        assert_eq!(chain(raw, "@cf/meta/llama-3.1-8b-instruct"), vec!["@cf/meta/llama-3.2-1b-instruct"]);
+    }
+
+    #[test]
+    fn per_model_fallback_chain_uses_the_wildcard_for_the_rest() {
+        let raw = r#"{ "@cf/meta/llama-3.1-8b-instruct": ["@cf/meta/llama-3.2-1b-instruct"], "*": ["@cf/meta/llama-3.1-8b-instruct"] }"#;
+        assert_eq!(chain(raw, "@cf/meta/llama-3.1-8b-instruct"), vec!["@cf/meta/llama-3.2-1b-instruct"]);
+        assert_eq!(chain(raw, "@cf/mistral/mistral-7b-instruct-v0.1"), vec!["@cf/meta/llama-3.1-8b-instruct"]);
+        assert!(chain(raw, "@cf/baai/bge-base-en-v1.5").is_empty());
+        assert!(chain("{ not json", "@cf/meta/llama-3.1-8b-instruct").is_empty());
+    }
+
+    #[test]
+    fn well_formed_unknown_ids_become_dynamic_models() {
+        let qwen = ModelRegistry::get_model("@cf/qwen/qwen1.5-14b-chat-awq").unwrap();
+        assert_eq!(qwen.category, ModelCategory::Llm);
+        assert!(!ModelRegistry::is_curated(&qwen.id));
+        assert_eq!(ModelRegistry::get_model("@hf/thebloke/some-embedding-model").unwrap().category, ModelCategory::Embedding);
+    }
+
+    #[test]
+    fn malformed_ids_are_not_models() {
+        for id in ["llama", "@cf/meta", "@cf/meta/llama/extra", "@cf//llama", "@cf/meta/llama 3", "cf/meta/llama"] {
+            assert!(ModelRegistry::get_model(id).is_none(), "{} was accepted", id);
+        }
+    }
+
+    #[test]
+    fn category_hints_only_apply_to_dynamic_models() {
+        let hinted = ModelRegistry::get_model_with_hint("@cf/vendor/mystery", Some(ModelCategory::Image)).unwrap();
+        assert_eq!(hinted.category, ModelCategory::Image);
+        let curated = ModelRegistry::get_model_with_hint("@cf/meta/llama-3.1-8b-instruct", Some(ModelCategory::Image)).unwrap();
+        assert_eq!(curated.category, ModelCategory::Llm);
+        assert_eq!(ModelRegistry::permissive_model("anything", None).id, "anything");
     }
 }
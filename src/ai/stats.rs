@@ -0,0 +1,129 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Estimated-vs-reported neuron counts per model, kept in the optional `STATS`
+//! KV namespace so the `estimate_neurons` formulas can be tuned from real data.
+//! Calls where the backend reports no count are kept apart, so the ratio only
+//! compares estimates against real figures.
+//! Updates are read-modify-write, so concurrent calls may occasionally drop a sample.
+
+use worker::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const BINDING: &str = "STATS";
+const KEY_PREFIX: &str = "neurons:";
+pub const NEURONS_URI: &str = "stats://neurons";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NeuronAccuracy {
+    /// Calls the backend reported a count for, and their estimated and actual totals.
+    pub calls: u64,
+    pub estimated: u64,
+    pub actual: u64,
+    /// Calls with no reported count, and their estimated total.
+    #[serde(default)]
+    pub unreported_calls: u64,
+    #[serde(default)]
+    pub unreported_estimated: u64,
+}
+
+impl NeuronAccuracy {
+    /// Add one call; `actual` is `None` when the backend didn't report a count.
+    pub fn add(&mut self, estimated: u32, actual: Option<u32>) {
+        match actual {
+            Some(actual) => {
+                self.calls += 1;
+                self.estimated += estimated as u64;
+                self.actual += actual as u64;
+            }
+            None => {
+                self.unreported_calls += 1;
+                self.unreported_estimated += estimated as u64;
+            }
+        }
+    }
+
+    /// Actual / estimated over reported calls; above 1.0 means the estimate is too low.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.estimated > 0).then(|| self.actual as f64 / self.estimated as f64)
+    }
+}
+
+/// Add one sample for `model`. Meant to run under `ctx.wait_until`, so failures
+/// are only logged.
+pub async fn record(env: Env, model: String, estimated: u32, actual: Option<u32>) {
+    let Ok(kv) = env.kv(BINDING) else {
+        return;
+    };
+
+    let key = format!("{}{}", KEY_PREFIX, model);
+    let result: Result<()> = async {
+        let mut stats = kv.get(&key).json::<NeuronAccuracy>().await?.unwrap_or_default();
+        stats.add(estimated, actual);
+
+        let text = serde_json::to_string(&stats)?;
+        kv.put(&key, text)?.execute().await?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        console_warn!("Failed to record neuron stats for {}: {}", model, e);
+    }
+}
+
+/// Per-model accuracy summary, or `None` when the `STATS` binding isn't configured.
+pub async fn neuron_report(env: &Env) -> Result<Option<serde_json::Value>> {
+    let Ok(kv) = env.kv(BINDING) else {
+        return Ok(None);
+    };
+
+    let mut models = serde_json::Map::new();
+    let keys = kv.list().prefix(KEY_PREFIX.to_string()).execute().await?.keys;
+    for key in keys {
+        let Some(stats) = kv.get(&key.name).json::<NeuronAccuracy>().await? else {
+            continue;
+        };
+        let model = key.name.trim_start_matches(KEY_PREFIX).to_string();
+        models.insert(model, json!({
+            "calls": stats.calls,
+            "estimated": stats.estimated,
+            "actual": stats.actual,
+            "ratio": stats.ratio(),
+            "unreported_calls": stats.unreported_calls,
+            "unreported_estimated": stats.unreported_estimated,
+        }));
+    }
+
+    Ok(Some(json!({ "models": models })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreported_calls_stay_out_of_the_ratio() {
+        let mut stats = NeuronAccuracy::default();
+        stats.add(100, Some(150));
+        stats.add(40, None);
+        assert_eq!((stats.calls, stats.estimated, stats.actual), (1, 100, 150));
+        assert_eq!((stats.unreported_calls, stats.unreported_estimated), (1, 40));
+        assert_eq!(stats.ratio(), Some(1.5));
+    }
+
+    #[test]
+    fn no_ratio_without_reported_calls() {
+        let mut stats = NeuronAccuracy::default();
+        stats.add(40, None);
+        assert_eq!(stats.ratio(), None);
+    }
+
+    #[test]
+    fn older_records_without_unreported_totals_still_load() {
+        let stats: NeuronAccuracy = serde_json::from_str(r#"{"calls":2,"estimated":10,"actual":12}"#).unwrap();
+        assert_eq!(stats.unreported_calls, 0);
+        assert_eq!(stats.ratio(), Some(1.2));
+    }
+}
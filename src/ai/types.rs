@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::ai::models::ModelCategory;
 
+/// Body of `POST /infer`.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AiRequest {
     pub model: String,
@@ -13,4 +16,88 @@ pub struct AiRequest {
 pub struct AiResponse {
     pub result: serde_json::Value,
     pub neurons_used: u32,
+    /// The pre-call estimate. `neurons_used` is the same number unless the backend
+    /// reported its own count.
+    #[serde(default)]
+    pub estimated_neurons: u32,
+    /// Whether `neurons_used` came from the backend rather than the estimate.
+    #[serde(default)]
+    pub neurons_reported: bool,
+    /// The concrete model id that served the request.
+    pub model_used: String,
+    /// The category the model was run as, including a `__category` hint for
+    /// dynamic models; `None` if it isn't known.
+    #[serde(default)]
+    pub category: Option<ModelCategory>,
+    /// Token counts as reported by the backend, or estimated from text lengths.
+    pub usage: Option<TokenUsage>,
+    /// Notes about how the input was adjusted before inference (e.g. clamped values).
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub meta: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl TokenUsage {
+    /// Read `{ prompt_tokens, completion_tokens }` from a backend `usage` object.
+    pub fn from_reported(usage: &serde_json::Value) -> Option<Self> {
+        let count = |key: &str| usage.get(key).and_then(|v| v.as_u64()).map(|v| v as u32);
+        Some(Self {
+            prompt_tokens: count("prompt_tokens")?,
+            completion_tokens: count("completion_tokens").unwrap_or(0),
+        })
+    }
+
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// A streaming inference in progress: `body` is the backend's `ReadableStream`
+/// of server-sent events, each carrying a `{ "response": "..." }` delta.
+pub struct AiStream {
+    pub body: wasm_bindgen::JsValue,
+    pub estimated_neurons: u32,
+    pub model_used: String,
+}
+
+#[derive(Debug)]
+pub enum AiError {
+    /// The caller's input was rejected before reaching the backend.
+    InvalidInput(String),
+    /// A URL argument was refused or couldn't be fetched.
+    InvalidUrl(String),
+    /// The model id isn't curated and doesn't look like a Workers AI model.
+    UnknownModel(String),
+    /// Anything that went wrong while talking to the AI binding.
+    Backend(String),
+    /// The circuit breaker is open, so the backend wasn't called.
+    Unavailable(String),
+}
+
+impl fmt::Display for AiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AiError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            AiError::InvalidUrl(msg) => write!(f, "Invalid URL input: {}", msg),
+            AiError::UnknownModel(id) => write!(f, "Unknown model: {}", id),
+            AiError::Backend(msg) => write!(f, "{}", msg),
+            AiError::Unavailable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<worker::Error> for AiError {
+    fn from(e: worker::Error) -> Self {
+        AiError::Backend(e.to_string())
+    }
 }
@@ -0,0 +1,117 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::str::FromStr;
+use worker::{console_warn, Env};
+
+/// Read a plain-text variable, treating unset and empty values as absent.
+pub fn var(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|v| v.to_string())
+        .filter(|v| !v.trim().is_empty())
+}
+
+/// Read a boolean flag ("1", "true", "yes", "on"), falling back to `default` when unset.
+pub fn flag(env: &Env, name: &str, default: bool) -> bool {
+    match var(env, name) {
+        Some(v) => matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        None => default,
+    }
+}
+
+/// Read a numeric variable, warning and falling back to `default` if it doesn't parse.
+pub fn number<T: FromStr>(env: &Env, name: &str, default: T) -> T {
+    match var(env, name) {
+        Some(v) => v.trim().parse().unwrap_or_else(|_| {
+            console_warn!("Ignoring {}: '{}' is not a valid number", name, v);
+            default
+        }),
+        None => default,
+    }
+}
+
+/// `READ_ONLY`: discovery keeps working, but nothing that runs a model is allowed.
+pub fn read_only(env: &Env) -> bool {
+    flag(env, "READ_ONLY", false)
+}
+
+/// `ALLOWED_METHODS`: the JSON-RPC methods this deployment answers, or `None`
+/// (everything) when unset. `initialize` and `ping` are always answered.
+pub fn allowed_methods(env: &Env) -> Option<Vec<String>> {
+    let methods: Vec<String> = var(env, "ALLOWED_METHODS")?
+        .split(',')
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .collect();
+    Some(methods)
+}
+
+/// Distinct non-curated models one MCP session, or one client IP without an
+/// initialized session, may call (`MAX_DYNAMIC_MODELS`, default 50, 0 = unlimited).
+pub fn max_dynamic_models(env: &Env) -> usize {
+    number(env, "MAX_DYNAMIC_MODELS", 50)
+}
+
+/// Longest an MCP request may run (`AI_TIMEOUT_MS`, default 30 s).
+pub fn ai_timeout_ms(env: &Env) -> u64 {
+    number(env, "AI_TIMEOUT_MS", 30_000)
+}
+
+/// Most entries in one JSON-RPC batch (`MAX_BATCH_SIZE`, default 20).
+pub fn max_batch_size(env: &Env) -> usize {
+    number(env, "MAX_BATCH_SIZE", 20)
+}
+
+/// Embedding sub-calls or `n` completions in flight at once (`BATCH_CONCURRENCY`, default 4).
+pub fn batch_concurrency(env: &Env) -> usize {
+    number(env, "BATCH_CONCURRENCY", 4usize).max(1)
+}
+
+/// Deepest nesting allowed in `tools/call` arguments (`MAX_ARGUMENT_DEPTH`, default 32).
+pub fn max_argument_depth(env: &Env) -> usize {
+    number(env, "MAX_ARGUMENT_DEPTH", 32)
+}
+
+/// Most object keys allowed across all of a `tools/call`'s arguments (`MAX_ARGUMENT_KEYS`, default 1000).
+pub fn max_argument_keys(env: &Env) -> usize {
+    number(env, "MAX_ARGUMENT_KEYS", 1000)
+}
+
+/// Most content blocks in one tool result (`MAX_CONTENT_BLOCKS`, default 16, 0 = unlimited).
+pub fn max_content_blocks(env: &Env) -> usize {
+    number(env, "MAX_CONTENT_BLOCKS", 16)
+}
+
+/// Most neurons one `tools/call` with `n` completions may be estimated to cost
+/// (`MAX_CALL_NEURONS`, default 10,000, the free tier's daily allowance; 0 = unlimited).
+pub fn max_call_neurons(env: &Env) -> u32 {
+    number(env, "MAX_CALL_NEURONS", 10_000)
+}
+
+/// Largest serialized tool result (`MAX_RESULT_BYTES`, default 8 MiB, 0 = unlimited).
+pub fn max_result_bytes(env: &Env) -> usize {
+    number(env, "MAX_RESULT_BYTES", 8 * 1024 * 1024)
+}
+
+/// Largest accepted upload or fetched media file (`MAX_BODY_BYTES`, default 10 MiB).
+pub fn max_body_bytes(env: &Env) -> usize {
+    number(env, "MAX_BODY_BYTES", 10 * 1024 * 1024)
+}
+
+/// `NEURON_MULTIPLIER` (default 1.0), applied to neuron counts shown to callers.
+/// Must be positive and finite; anything else is ignored with a warning.
+pub fn neuron_multiplier(env: &Env) -> f64 {
+    let multiplier = number(env, "NEURON_MULTIPLIER", 1.0f64);
+    if multiplier.is_finite() && multiplier > 0.0 {
+        multiplier
+    } else {
+        console_warn!("Ignoring NEURON_MULTIPLIER: {} is not a positive finite number", multiplier);
+        1.0
+    }
+}
+
+/// The neuron figure reported to callers. Budgets and stats keep using raw counts.
+pub fn billed_neurons(env: &Env, raw: u32) -> u32 {
+    (raw as f64 * neuron_multiplier(env)).round().min(u32::MAX as f64) as u32
+}
@@ -4,9 +4,25 @@
 use worker::*;
 
 mod ai;
+mod config;
 mod mcp;
+mod openai;
+mod tasks;
+mod usage;
+mod warmup;
 
-use mcp::{JsonRpcRequest, McpServer};
+use futures_util::future::{select, AbortHandle, Abortable, Either};
+use std::pin::pin;
+use mcp::{JsonRpcError, JsonRpcRequest, JsonRpcResponse, McpServer};
+
+/// Response headers browser clients may read; add new custom headers here.
+const EXPOSED_HEADERS: &[&str] = &["Mcp-Session-Id", "ETag"];
+
+/// `GET` on this plus a completion id resumes a buffered chat completion stream.
+const RESUME_PREFIX: &str = "/v1/chat/completions/";
+
+/// Shortest per-request timeout a client deadline hint can ask for.
+const MIN_TIMEOUT_MS: u64 = 1_000;
 
 fn cors_headers() -> Headers {
     let headers = Headers::new();
@@ -14,21 +30,29 @@ fn cors_headers() -> Headers {
     let _ = headers.set("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS");
     let _ = headers.set(
         "Access-Control-Allow-Headers",
-        "Content-Type, Authorization, Mcp-Session-Id, Mcp-Protocol-Version",
+        "Content-Type, Authorization, Mcp-Session-Id, Mcp-Protocol-Version, X-Timeout-Ms, Last-Event-ID",
     );
+    let _ = headers.set("Access-Control-Expose-Headers", &EXPOSED_HEADERS.join(", "));
     headers
 }
 
-/// Build a JSON response with CORS headers, preserving Content-Type.
+/// Build a JSON response carrying exactly one `Content-Type: application/json`
+/// plus the CORS headers.
 fn json_response<B: serde::Serialize>(value: &B) -> Result<Response> {
-    let headers = cors_headers();
+    let mut response = Response::from_json(value)?;
+    let headers = response.headers_mut();
+    for (name, value) in cors_headers().entries() {
+        headers.set(&name, &value)?;
+    }
+    // `set` replaces rather than appends, so there's never a second value
     headers.set("Content-Type", "application/json")?;
-    Response::from_json(value).map(|r| r.with_headers(headers))
+    Ok(response)
 }
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
+    warmup::schedule(&env, &ctx);
 
     if req.method() == Method::Options {
         return Ok(Response::builder()
@@ -41,14 +65,63 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     let path = url.path();
 
     match (req.method(), path.as_ref()) {
+        (Method::Get, "/health") if url.query_pairs().any(|(k, v)| k == "protocols" && v == "1") => {
+            json_response(&mcp::server::SUPPORTED_PROTOCOL_VERSIONS)
+        }
         (Method::Get, "/health") => {
             let headers = cors_headers();
             headers.set("Content-Type", "text/plain")?;
             Response::ok("OK").map(|r| r.with_headers(headers))
         }
-        (Method::Post, "/mcp") => handle_mcp(req, env).await,
-        // GET and DELETE on /mcp: 405 per MCP spec
-        (Method::Get | Method::Delete, "/mcp") => Ok(Response::builder()
+        (Method::Get, "/ready") => handle_ready(&env),
+        (Method::Post, "/mcp") => handle_mcp(req, env, ctx).await,
+        (Method::Get, "/resources") => {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            handle_resource_get(&req, &env).await
+        }
+        (Method::Post, "/v1/chat/completions" | "/v1/images/describe" | "/v1/images/generations")
+            if config::read_only(&env) =>
+        {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            error_json(503, "Server is in read-only mode")
+        }
+        (Method::Post, "/infer") => {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            handle_infer(req, env, ctx).await
+        }
+        (Method::Post, "/v1/chat/completions") => {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            openai::handle_chat_completions(req, env).await
+        }
+        (Method::Post, "/v1/images/describe") => {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            openai::handle_image_describe(req, env).await
+        }
+        (Method::Post, "/v1/images/generations") => {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            openai::handle_image_generations(req, env).await
+        }
+        (Method::Get, path) if path.starts_with(RESUME_PREFIX) => {
+            if !authorized(&req, &env)? {
+                return unauthorized();
+            }
+            openai::resume::handle_resume(&req, &env, &path[RESUME_PREFIX.len()..]).await
+        }
+        (Method::Delete, "/mcp") => handle_mcp_delete(&req, &env).await,
+        // GET on /mcp: 405 per MCP spec (no server-initiated stream)
+        (Method::Get, "/mcp") => Ok(Response::builder()
             .with_headers(cors_headers())
             .with_status(405)
             .empty()),
@@ -59,8 +132,16 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     }
 }
 
-async fn handle_mcp(mut req: Request, env: Env) -> Result<Response> {
-    // Optional authentication
+/// Daily cron (see `wrangler.toml`): close out the usage window.
+#[event(scheduled)]
+async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    if let Err(e) = usage::roll_over(&env, event.schedule() as u64).await {
+        console_error!("Usage rollover failed: {}", e);
+    }
+}
+
+/// Optional bearer-token authentication, enabled by setting `MCP_AUTH_TOKEN`.
+fn authorized(req: &Request, env: &Env) -> Result<bool> {
     if let Ok(secret) = env.secret("MCP_AUTH_TOKEN") {
         let auth_token = secret.to_string();
         let provided_token = req
@@ -68,22 +149,124 @@ async fn handle_mcp(mut req: Request, env: Env) -> Result<Response> {
             .get("Authorization")?
             .and_then(|h| h.strip_prefix("Bearer ").map(|s| s.to_string()));
 
-        if provided_token.as_deref() != Some(auth_token.as_str()) {
-            return Response::error("Unauthorized", 401).map(|r| r.with_headers(cors_headers()));
+        let ok = provided_token.as_deref() == Some(auth_token.as_str());
+        if !ok {
+            console_warn!("Rejected unauthorized request from {}", client_ip(req, env).as_deref().unwrap_or("unknown"));
         }
+        return Ok(ok);
     }
+    Ok(true)
+}
 
-    let json_req: JsonRpcRequest = match req.json().await {
-        Ok(req) => req,
+/// The caller's IP from the header named by `CLIENT_IP_HEADER` (default
+/// `CF-Connecting-IP`). For list-valued headers like `X-Forwarded-For` the
+/// first (client-most) entry is used.
+fn client_ip(req: &Request, env: &Env) -> Option<String> {
+    let header = config::var(env, "CLIENT_IP_HEADER").unwrap_or_else(|| "CF-Connecting-IP".to_string());
+    let value = req.headers().get(&header).ok().flatten()?;
+    value
+        .split(',')
+        .next()
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+}
+
+fn unauthorized() -> Result<Response> {
+    Response::error("Unauthorized", 401).map(|r| r.with_headers(cors_headers()))
+}
+
+/// `GET /resources?uri=...[&format=...]`: plain-HTTP access to MCP resources, honoring `If-None-Match`.
+async fn handle_resource_get(req: &Request, env: &Env) -> Result<Response> {
+    let url = req.url()?;
+    let query = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+    let uri = query("uri");
+    let format = match query("format") {
+        Some(format) => match serde_json::from_value(serde_json::Value::String(format)) {
+            Ok(format) => format,
+            Err(_) => return Response::error("format must be json, compact, or markdown", 400)
+                .map(|r| r.with_headers(cors_headers())),
+        },
+        None => mcp::ResourceFormat::default(),
+    };
+
+    let contents = match uri.as_deref() {
+        Some(uri) => mcp::resources::read_resource(env, uri, format).await,
+        None => None,
+    };
+    let Some(content) = contents.and_then(|c| c.contents.into_iter().next()) else {
+        return Response::error("Resource not found", 404).map(|r| r.with_headers(cors_headers()));
+    };
+
+    let etag = mcp::resources::etag(&content.text);
+    let headers = cors_headers();
+    headers.set("ETag", &etag)?;
+
+    let not_modified = req
+        .headers()
+        .get("If-None-Match")?
+        .is_some_and(|h| h.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+    if not_modified {
+        return Ok(Response::builder()
+            .with_headers(headers)
+            .with_status(304)
+            .empty());
+    }
+
+    headers.set("Content-Type", &content.mime_type)?;
+    Response::ok(content.text).map(|r| r.with_headers(headers))
+}
+
+async fn handle_mcp(mut req: Request, env: Env, ctx: Context) -> Result<Response> {
+    if !authorized(&req, &env)? {
+        return unauthorized();
+    }
+
+    let body: serde_json::Value = match req.json().await {
+        Ok(body) => body,
         Err(e) => {
             console_log!("Failed to parse request: {}", e);
-            return Response::error("Invalid JSON-RPC request", 400)
-                .map(|r| r.with_headers(cors_headers()));
+            return json_response(&JsonRpcResponse::from_error(None, JsonRpcError::parse_error("Parse error")));
         }
     };
 
-    match McpServer::handle_request(&env, json_req).await {
-        Some(response) => json_response(&response),
+    let session_id = req.headers().get("Mcp-Session-Id")?;
+    let caller = mcp_caller(&req, &env, session_id.as_deref()).await;
+    // A closed session stays closed; the client has to initialize a new one
+    if caller.status.closed {
+        return Response::error("Session not found", 404).map(|r| r.with_headers(cors_headers()));
+    }
+    let header_timeout_ms = req.headers().get("X-Timeout-Ms")?.and_then(|v| v.trim().parse().ok());
+
+    if let Some(batch) = body.as_array() {
+        return handle_mcp_batch(&env, &ctx, session_id.as_deref(), &caller, header_timeout_ms, batch).await;
+    }
+
+    let json_req = match mcp::validate_envelope(&body) {
+        Ok(req) => req,
+        Err(error) => return json_response(&JsonRpcResponse::from_error(mcp::envelope_id(&body), error)),
+    };
+
+    // Transcriptions answer over SSE when the client accepts it. Workers AI
+    // doesn't stream audio models yet, so the stream is one final `message` event.
+    let accepts_sse = req
+        .headers()
+        .get("Accept")?
+        .is_some_and(|a| a.contains("text/event-stream"));
+    let sse = accepts_sse && McpServer::is_audio_tool_call(&env, &json_req);
+
+    let new_session = (json_req.method == "initialize").then(mcp::session::new_session_id);
+    let active_session = new_session.as_deref().or(session_id.as_deref());
+    let response = run_tracked(&env, &ctx, session_id.as_deref(), active_session, &caller, header_timeout_ms, json_req).await;
+
+    match response {
+        Some(response) => {
+            let mut response = if sse { sse_response(&response)? } else { json_response(&response)? };
+            if let Some(session) = new_session {
+                response.headers_mut().set("Mcp-Session-Id", &session)?;
+            }
+            Ok(response)
+        }
         None => {
             // Notifications get HTTP 202 with no body
             Ok(Response::builder()
@@ -93,3 +276,220 @@ async fn handle_mcp(mut req: Request, env: Env) -> Result<Response> {
         }
     }
 }
+
+/// Who sent `req`, and what the session store knows about `session_id`.
+async fn mcp_caller(req: &Request, env: &Env, session_id: Option<&str>) -> mcp::session::Caller {
+    mcp::session::Caller {
+        status: match session_id {
+            Some(session) => mcp::session::status(env, session).await,
+            None => mcp::session::Status::default(),
+        },
+        ip: client_ip(req, env),
+    }
+}
+
+/// `POST /infer`: plain JSON `{ model, input }` in, `AiResponse` out, for callers
+/// that don't want the JSON-RPC envelope. Goes through the same checks as an
+/// MCP `tools/call`, with an `Mcp-Session-Id` header counting as that session.
+async fn handle_infer(mut req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let body: ai::types::AiRequest = match req.json().await {
+        Ok(body) => body,
+        Err(e) => return error_json(400, &format!("Invalid request body: {}", e)),
+    };
+
+    let session_id = req.headers().get("Mcp-Session-Id")?;
+    let caller = mcp_caller(&req, &env, session_id.as_deref()).await;
+    if let Err(e) = McpServer::check_call(&env, session_id.as_deref(), &caller, &body.input, Some((&body.model, 1))).await {
+        let status = match e.code {
+            -32602 => 400,
+            -32601 | -32800 => 404,
+            _ => 503,
+        };
+        return error_json(status, &e.message);
+    }
+
+    let mut response = match ai::AiBridge::run_inference(&env, &body.model, body.input, None).await {
+        Ok(response) => response,
+        Err(e @ (ai::AiError::InvalidInput(_) | ai::AiError::InvalidUrl(_))) => return error_json(400, &e.to_string()),
+        Err(e @ ai::AiError::UnknownModel(_)) => return error_json(404, &e.to_string()),
+        Err(e @ ai::AiError::Backend(_)) => return error_json(502, &format!("AI inference failed: {}", e)),
+        Err(e @ ai::AiError::Unavailable(_)) => return error_json(503, &e.to_string()),
+    };
+
+    ctx.wait_until(usage::record(env.clone(), caller.usage_client(session_id.as_deref()), response.neurons_used));
+
+    response.neurons_used = config::billed_neurons(&env, response.neurons_used);
+    json_response(&response)
+}
+
+/// Readiness, as opposed to `/health`'s liveness: 503 until the AI binding is
+/// there and, with `READY_MAX_IDLE_MS` set, until a backend call has succeeded in
+/// this isolate within that many milliseconds (warm-up counts).
+fn handle_ready(env: &Env) -> Result<Response> {
+    let ai_binding = ai::AiBridge::has_binding(env);
+
+    let max_idle_ms = config::number(env, "READY_MAX_IDLE_MS", 0u64);
+    let last_success_ms = ai::AiBridge::last_success_ms();
+    let recent_inference = max_idle_ms == 0
+        || last_success_ms.is_some_and(|t| Date::now().as_millis().saturating_sub(t) <= max_idle_ms);
+
+    let ready = ai_binding && recent_inference;
+    json_response(&serde_json::json!({
+        "ready": ready,
+        "checks": {
+            "ai_binding": ai_binding,
+            "recent_inference": recent_inference,
+        },
+        "last_success_ms": last_success_ms,
+    }))
+    .map(|r| r.with_status(if ready { 200 } else { 503 }))
+}
+
+fn error_json(status: u16, message: &str) -> Result<Response> {
+    json_response(&serde_json::json!({ "error": message })).map(|r| r.with_status(status))
+}
+
+/// A JSON-RPC batch: every entry is answered in order, notifications excepted.
+/// The size is checked against `MAX_BATCH_SIZE` (default 20) before anything runs.
+async fn handle_mcp_batch(
+    env: &Env,
+    ctx: &Context,
+    session_id: Option<&str>,
+    caller: &mcp::session::Caller,
+    header_timeout_ms: Option<u64>,
+    batch: &[serde_json::Value],
+) -> Result<Response> {
+    let max_batch_size = config::max_batch_size(env);
+    if batch.is_empty() {
+        return json_response(&JsonRpcResponse::from_error(None, JsonRpcError::invalid_request("Empty batch")));
+    }
+    if batch.len() > max_batch_size {
+        let error = JsonRpcError::invalid_request(format!(
+            "Batch has {} entries; the limit is {} (MAX_BATCH_SIZE)",
+            batch.len(),
+            max_batch_size
+        ));
+        return json_response(&JsonRpcResponse::from_error(None, error));
+    }
+
+    let mut responses = Vec::with_capacity(batch.len());
+    for entry in batch {
+        let response = match mcp::validate_envelope(entry) {
+            // Sessions are created by a standalone initialize only
+            Ok(json_req) if json_req.method == "initialize" => Some(JsonRpcResponse::from_error(
+                json_req.id,
+                JsonRpcError::invalid_request("initialize must not be part of a batch"),
+            )),
+            Ok(json_req) => run_tracked(env, ctx, session_id, session_id, caller, header_timeout_ms, json_req).await,
+            Err(error) => Some(JsonRpcResponse::from_error(mcp::envelope_id(entry), error)),
+        };
+        responses.extend(response);
+    }
+
+    if responses.is_empty() {
+        // A batch of only notifications gets HTTP 202 with no body
+        return Ok(Response::builder()
+            .with_status(202)
+            .with_headers(cors_headers())
+            .empty());
+    }
+    json_response(&responses)
+}
+
+/// Handle one request while tracking its id in the caller's session: ids must be
+/// unique among in-flight requests, and closing the session aborts the request.
+/// The request also gives up after its timeout (see `request_timeout_ms`).
+async fn run_tracked(
+    env: &Env,
+    ctx: &Context,
+    session_id: Option<&str>,
+    active_session: Option<&str>,
+    caller: &mcp::session::Caller,
+    header_timeout_ms: Option<u64>,
+    json_req: JsonRpcRequest,
+) -> Option<JsonRpcResponse> {
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    let tracked_id = match (session_id, &json_req.id) {
+        (Some(session), Some(id)) if !id.is_null() => {
+            if !mcp::session::begin_request(session, id, abort_handle) {
+                return Some(JsonRpcResponse::from_error(
+                    Some(id.clone()),
+                    JsonRpcError::invalid_request(format!("Request id {} is already in flight in this session", id)),
+                ));
+            }
+            Some(id.clone())
+        }
+        _ => None,
+    };
+
+    let request_id = json_req.id.clone();
+    let timeout_ms = request_timeout_ms(env, header_timeout_ms, &json_req);
+    let deadline_ms = Date::now().as_millis() + timeout_ms;
+    let work = Abortable::new(
+        McpServer::handle_request(env, ctx, active_session, caller, deadline_ms, json_req),
+        abort_registration,
+    );
+    let deadline = Delay::from(std::time::Duration::from_millis(timeout_ms));
+
+    let response = match select(pin!(work), pin!(deadline)).await {
+        Either::Left((Ok(response), _)) => response,
+        Either::Left((Err(_), _)) => {
+            Some(JsonRpcResponse::from_error(request_id, JsonRpcError::new(
+                -32800,
+                "Request cancelled: session closed (a model call already under way still finishes and may be billed)",
+            )))
+        }
+        Either::Right(_) => Some(JsonRpcResponse::from_error(
+            request_id,
+            JsonRpcError::internal(format!("Request timed out after {}ms", timeout_ms)),
+        )),
+    };
+
+    if let (Some(session), Some(id)) = (session_id, &tracked_id) {
+        mcp::session::end_request(session, id);
+    }
+
+    response
+}
+
+/// The client's deadline hint (`_meta.timeoutMs` in the params, else the
+/// `X-Timeout-Ms` header), clamped to `[MIN_TIMEOUT_MS, AI_TIMEOUT_MS]`; without
+/// one, `AI_TIMEOUT_MS` (default 30000).
+fn request_timeout_ms(env: &Env, header_timeout_ms: Option<u64>, req: &JsonRpcRequest) -> u64 {
+    let max = config::ai_timeout_ms(env).max(MIN_TIMEOUT_MS);
+    req.params
+        .as_ref()
+        .and_then(|p| p.pointer("/_meta/timeoutMs"))
+        .and_then(|v| v.as_u64())
+        .or(header_timeout_ms)
+        .map_or(max, |hint| hint.clamp(MIN_TIMEOUT_MS, max))
+}
+
+/// A single JSON-RPC response as a one-event `text/event-stream`.
+fn sse_response(response: &JsonRpcResponse) -> Result<Response> {
+    let headers = cors_headers();
+    headers.set("Content-Type", "text/event-stream")?;
+    headers.set("Cache-Control", "no-cache")?;
+    let body = format!("event: message\ndata: {}\n\n", serde_json::to_string(response)?);
+    Response::ok(body).map(|r| r.with_headers(headers))
+}
+
+/// `DELETE /mcp`: end the session named by `Mcp-Session-Id`, aborting its in-flight requests.
+async fn handle_mcp_delete(req: &Request, env: &Env) -> Result<Response> {
+    if !authorized(req, env)? {
+        return unauthorized();
+    }
+
+    let Some(session_id) = req.headers().get("Mcp-Session-Id")? else {
+        return Response::error("Missing Mcp-Session-Id", 400).map(|r| r.with_headers(cors_headers()));
+    };
+
+    if !mcp::session::close(env, &session_id).await {
+        return Response::error("Unknown session", 404).map(|r| r.with_headers(cors_headers()));
+    }
+
+    Ok(Response::builder()
+        .with_headers(cors_headers())
+        .with_status(204)
+        .empty())
+}
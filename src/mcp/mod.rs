@@ -5,6 +5,8 @@ pub mod protocol;
 pub mod server;
 pub mod tools;
 pub mod resources;
+pub mod prompts;
+pub mod session;
 
 pub use protocol::*;
 pub use server::McpServer;
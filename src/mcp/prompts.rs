@@ -0,0 +1,52 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! MCP prompts backed by the `PROMPT_MODELS` templates in `crate::tasks::prompt`.
+
+use worker::Env;
+use crate::mcp::protocol::*;
+use crate::tasks::prompt;
+use serde_json::json;
+
+pub fn list_prompts(env: &Env) -> PromptsList {
+    let prompts = prompt::configured(env)
+        .into_iter()
+        .map(|(name, prompt)| Prompt {
+            name,
+            description: Some(describe(&prompt)),
+            arguments: prompt.arguments()
+                .into_iter()
+                .map(|argument| PromptArgument { name: argument, required: true })
+                .collect(),
+        })
+        .collect();
+
+    PromptsList { prompts }
+}
+
+/// Render a prompt as a single user message. `_meta.model` names the model it's
+/// meant for; `__prompt` runs it there directly.
+pub fn get_prompt(env: &Env, params: GetPromptParams) -> Result<GetPromptResult, JsonRpcError> {
+    let prompt = prompt::configured(env)
+        .remove(&params.name)
+        .ok_or_else(|| JsonRpcError::invalid_params(format!("Unknown prompt: {}", params.name)))?;
+
+    let text = prompt.render(&params.arguments.unwrap_or_default())
+        .map_err(|e| JsonRpcError::invalid_params(e.to_string()))?;
+
+    Ok(GetPromptResult {
+        description: Some(describe(&prompt)),
+        messages: vec![PromptMessage {
+            role: "user".to_string(),
+            content: ContentBlock::Text { text },
+        }],
+        meta: Some(json!({ "model": prompt.model })),
+    })
+}
+
+fn describe(prompt: &prompt::PromptModel) -> String {
+    match &prompt.description {
+        Some(description) => format!("{} (runs on {})", description, prompt.model),
+        None => format!("Runs on {}", prompt.model),
+    }
+}
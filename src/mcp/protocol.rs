@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonRpcRequest {
@@ -12,6 +12,51 @@ pub struct JsonRpcRequest {
     pub params: Option<Value>,
 }
 
+/// Check a raw JSON-RPC envelope field by field, so each defect gets a precise
+/// error instead of serde's generic one.
+pub fn validate_envelope(value: &Value) -> Result<JsonRpcRequest, JsonRpcError> {
+    let Some(obj) = value.as_object() else {
+        return Err(JsonRpcError::invalid_request("Request must be a JSON object"));
+    };
+
+    let id = match obj.get("id") {
+        None => None,
+        Some(id @ (Value::String(_) | Value::Number(_) | Value::Null)) => Some(id.clone()),
+        Some(_) => return Err(JsonRpcError::invalid_request("'id' must be a string, number, or null")),
+    };
+
+    match obj.get("jsonrpc") {
+        Some(Value::String(v)) if v == "2.0" => {}
+        Some(_) => return Err(JsonRpcError::invalid_request("'jsonrpc' must be exactly \"2.0\"")),
+        None => return Err(JsonRpcError::invalid_request("Missing 'jsonrpc' member")),
+    }
+
+    let method = match obj.get("method") {
+        Some(Value::String(m)) if !m.is_empty() => m.clone(),
+        Some(Value::String(_)) => return Err(JsonRpcError::invalid_request("'method' must not be empty")),
+        Some(_) => return Err(JsonRpcError::invalid_request("'method' must be a string")),
+        None => return Err(JsonRpcError::invalid_request("Missing 'method' member")),
+    };
+
+    let params = match obj.get("params") {
+        None => None,
+        Some(p @ (Value::Object(_) | Value::Array(_))) => Some(p.clone()),
+        Some(_) => return Err(JsonRpcError::invalid_params("'params' must be an object or array")),
+    };
+
+    Ok(JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        id,
+        method,
+        params,
+    })
+}
+
+/// The envelope's id if it's a valid one, for echoing back in an error response.
+pub fn envelope_id(value: &Value) -> Option<Value> {
+    value.get("id").filter(|id| id.is_string() || id.is_number()).cloned()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonRpcResponse {
     pub jsonrpc: String,
@@ -41,19 +86,49 @@ impl JsonRpcResponse {
     }
 
     pub fn error(id: Option<Value>, code: i32, message: String) -> Self {
+        Self::from_error(id, JsonRpcError::new(code, message))
+    }
+
+    pub fn from_error(id: Option<Value>, error: JsonRpcError) -> Self {
         Self {
             jsonrpc: "2.0".to_string(),
             id,
             result: None,
-            error: Some(JsonRpcError {
-                code,
-                message,
-                data: None,
-            }),
+            error: Some(error),
         }
     }
 }
 
+impl JsonRpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// -32700: the body isn't valid JSON.
+    pub fn parse_error(message: impl Into<String>) -> Self {
+        Self::new(-32700, message)
+    }
+
+    /// -32600: the request envelope itself is unacceptable.
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(-32600, message)
+    }
+
+    /// -32602: the method exists but its params were rejected.
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(-32602, message)
+    }
+
+    /// -32603: anything that went wrong while handling an otherwise valid request.
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(-32603, message)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InitializeResult {
     #[serde(rename = "protocolVersion")]
@@ -105,13 +180,27 @@ pub struct ToolsList {
 pub struct CallToolParams {
     pub name: String,
     pub arguments: Option<Value>,
+    /// Deprecated alias for `arguments`, matching `AiRequest::input`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<Value>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ListToolsParams {
+    /// Non-standard: only list models whose base cost fits this many neurons.
+    pub max_neurons: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ToolResult {
     pub content: Vec<ContentBlock>,
+    /// The result as a JSON object, for clients that advertised support for it.
+    #[serde(rename = "structuredContent", skip_serializing_if = "Option::is_none")]
+    pub structured_content: Option<Value>,
     #[serde(rename = "isError", skip_serializing_if = "Option::is_none")]
     pub is_error: Option<bool>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -121,6 +210,46 @@ pub enum ContentBlock {
     Text { text: String },
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptsList {
+    pub prompts: Vec<Prompt>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetPromptParams {
+    pub name: String,
+    pub arguments: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetPromptResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub messages: Vec<PromptMessage>,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: ContentBlock,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Resource {
     pub uri: String,
@@ -139,6 +268,28 @@ pub struct ResourcesList {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReadResourceParams {
     pub uri: String,
+    /// Non-standard: how JSON resources are rendered.
+    #[serde(default)]
+    pub format: ResourceFormat,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadResourceBatchParams {
+    pub uris: Vec<String>,
+    #[serde(default)]
+    pub format: ResourceFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceFormat {
+    /// Pretty-printed JSON.
+    #[default]
+    Json,
+    /// JSON on one line.
+    Compact,
+    /// Human-readable text; model cards list their fields with the schema in a fenced block.
+    Markdown,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -152,4 +303,60 @@ pub struct ResourceContent {
     #[serde(rename = "mimeType")]
     pub mime_type: String,
     pub text: String,
+    #[serde(rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rejected(value: Value) -> (i32, String) {
+        let error = validate_envelope(&value).unwrap_err();
+        (error.code, error.message)
+    }
+
+    #[test]
+    fn accepts_a_well_formed_request() {
+        let req = validate_envelope(&json!({
+            "jsonrpc": "2.0", "id": 7, "method": "tools/list", "params": { "cursor": null }
+        }))
+        .unwrap();
+        assert_eq!(req.id, Some(json!(7)));
+        assert_eq!(req.method, "tools/list");
+        assert_eq!(req.params, Some(json!({ "cursor": null })));
+    }
+
+    #[test]
+    fn notifications_have_no_id_and_params_are_optional() {
+        let req = validate_envelope(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" })).unwrap();
+        assert_eq!(req.id, None);
+        assert_eq!(req.params, None);
+    }
+
+    #[test]
+    fn bad_envelopes_are_invalid_requests() {
+        assert_eq!(rejected(json!([1])).0, -32600);
+        assert_eq!(rejected(json!({ "jsonrpc": "2.0", "id": {}, "method": "ping" })).0, -32600);
+        assert_eq!(rejected(json!({ "jsonrpc": "1.0", "id": 1, "method": "ping" })).1, "'jsonrpc' must be exactly \"2.0\"");
+        assert_eq!(rejected(json!({ "id": 1, "method": "ping" })).1, "Missing 'jsonrpc' member");
+        assert_eq!(rejected(json!({ "jsonrpc": "2.0", "id": 1 })).1, "Missing 'method' member");
+        assert_eq!(rejected(json!({ "jsonrpc": "2.0", "id": 1, "method": "" })).1, "'method' must not be empty");
+        assert_eq!(rejected(json!({ "jsonrpc": "2.0", "id": 1, "method": 5 })).1, "'method' must be a string");
+    }
+
+    #[test]
+    fn scalar_params_are_invalid_params() {
+        let (code, _) = rejected(json!({ "jsonrpc": "2.0", "id": 1, "method": "ping", "params": "x" }));
+        assert_eq!(code, -32602);
+    }
+
+    #[test]
+    fn envelope_id_only_echoes_valid_ids() {
+        assert_eq!(envelope_id(&json!({ "id": "a" })), Some(json!("a")));
+        assert_eq!(envelope_id(&json!({ "id": 3 })), Some(json!(3)));
+        assert_eq!(envelope_id(&json!({ "id": [1] })), None);
+        assert_eq!(envelope_id(&json!("not an object")), None);
+    }
 }
@@ -1,15 +1,30 @@
 // Copyright (C) 2026 Jade
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::ai::ModelRegistry;
+use worker::Env;
+use crate::ai::{stats, ModelRegistry};
+use crate::ai::models::{ModelCategory, ModelInfo};
+use crate::config;
 use crate::mcp::protocol::*;
+use crate::mcp::server::{McpServer, PROTOCOL_VERSION, SUPPORTED_PROTOCOL_VERSIONS};
+use crate::openai::resume;
+use crate::tasks::TimeBudget;
+use crate::usage;
 use serde_json::json;
 
+pub const SERVER_CONFIG_URI: &str = "config://server";
+
 pub fn list_resources() -> ResourcesList {
-    let mut resources = vec![];
+    let mut resources = vec![Resource {
+        uri: SERVER_CONFIG_URI.to_string(),
+        name: "Server configuration".to_string(),
+        description: Some("Limits, default models, and enabled features of this deployment".to_string()),
+        mime_type: Some("application/json".to_string()),
+    }];
 
-    // Add model info resources
-    let models = ModelRegistry::get_all_models();
+    // Add model info resources, in the same order as tools/list
+    let mut models = ModelRegistry::get_all_models();
+    ModelRegistry::sort_for_listing(&mut models);
     for model in models {
         resources.push(Resource {
             uri: format!("model://{}", model.id),
@@ -19,12 +34,57 @@ pub fn list_resources() -> ResourcesList {
         });
     }
 
+    resources.push(Resource {
+        uri: usage::USAGE_URI.to_string(),
+        name: "Neuron usage today".to_string(),
+        description: Some("Neurons used per session in the current daily window (needs the STATS KV binding)".to_string()),
+        mime_type: Some("application/json".to_string()),
+    });
+
+    resources.push(Resource {
+        uri: stats::NEURONS_URI.to_string(),
+        name: "Neuron estimate accuracy".to_string(),
+        description: Some("Estimated vs backend-reported neurons per model (needs the STATS KV binding)".to_string()),
+        mime_type: Some("application/json".to_string()),
+    });
+
     ResourcesList { resources }
 }
 
-pub fn get_resource_content(uri: &str) -> Option<ResourceContents> {
+/// Look up any resource, including the ones that need bindings from `env`.
+pub async fn read_resource(env: &Env, uri: &str, format: ResourceFormat) -> Option<ResourceContents> {
+    if uri == SERVER_CONFIG_URI {
+        return Some(json_contents(uri, &server_config(env), format));
+    }
+
+    if uri == usage::USAGE_URI {
+        let report = match usage::usage_report(env).await {
+            Ok(Some(report)) => report,
+            Ok(None) => json!({ "enabled": false, "hint": "Bind a KV namespace as STATS to track usage" }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        return Some(json_contents(uri, &report, format));
+    }
+
+    if uri == stats::NEURONS_URI {
+        let report = match stats::neuron_report(env).await {
+            Ok(Some(report)) => report,
+            Ok(None) => json!({ "enabled": false, "hint": "Bind a KV namespace as STATS to collect neuron stats" }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        return Some(json_contents(uri, &report, format));
+    }
+
+    get_resource_content(env, uri, format)
+}
+
+pub fn get_resource_content(env: &Env, uri: &str, format: ResourceFormat) -> Option<ResourceContents> {
     if let Some(model_id) = uri.strip_prefix("model://") {
-        if let Some(model) = ModelRegistry::get_model(model_id) {
+        if let Some(model) = ModelRegistry::lookup(env, model_id) {
+            if format == ResourceFormat::Markdown {
+                return Some(contents(uri, "text/markdown", model_card(&model)));
+            }
+
             let info = json!({
                 "id": model.id,
                 "name": model.name,
@@ -34,15 +94,107 @@ pub fn get_resource_content(uri: &str) -> Option<ResourceContents> {
                 "input_schema": model.input_schema,
             });
 
-            return Some(ResourceContents {
-                contents: vec![ResourceContent {
-                    uri: uri.to_string(),
-                    mime_type: "application/json".to_string(),
-                    text: serde_json::to_string_pretty(&info).unwrap_or_else(|_| info.to_string()),
-                }],
-            });
+            return Some(json_contents(uri, &info, format));
         }
     }
 
     None
 }
+
+/// Which features are live, keyed by name, for the `__capabilities` tool. Only
+/// cheap checks of env vars and bindings; nothing here calls a model.
+pub fn capabilities(env: &Env) -> serde_json::Value {
+    let has_model = |id: &str| ModelRegistry::get_model(id).is_some();
+
+    json!({
+        "inference": !config::read_only(env),
+        "streaming": ModelRegistry::get_all_models().iter().any(|m| m.supports_streaming()),
+        "sse_transcription": true,
+        "resumable_streams": resume::enabled(env),
+        "vision": has_model("@cf/llava-hf/llava-1.5-7b-hf"),
+        "image_generation": ModelRegistry::get_all_models().iter().any(|m| m.category == ModelCategory::Image),
+        "summarize": true,
+        "translate": true,
+        "batch_requests": true,
+        "cost_breakdown": true,
+        "usage_stats": env.kv("STATS").is_ok(),
+        "debug": config::flag(env, "DEBUG_MODE", false),
+        "dynamic_models": ModelRegistry::dynamic_models(env),
+        "permissive_models": config::flag(env, "PERMISSIVE_MODELS", false),
+        "response_caching": false,
+        "r2_hosting": false,
+        "moderation": false,
+    })
+}
+
+/// How this deployment is tuned. Secrets are only ever reported as set or unset.
+fn server_config(env: &Env) -> serde_json::Value {
+    let default_models: serde_json::Map<String, serde_json::Value> = ModelCategory::ALL
+        .iter()
+        .map(|c| (c.as_str().to_string(), json!(ModelRegistry::default_for(env, c))))
+        .collect();
+
+    json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "supported_protocol_versions": SUPPORTED_PROTOCOL_VERSIONS,
+        "auth_required": env.secret("MCP_AUTH_TOKEN").is_ok(),
+        "limits": {
+            "max_body_bytes": config::max_body_bytes(env),
+            "request_budget_ms": TimeBudget::limit_ms(env),
+        },
+        "strict_params": config::flag(env, "STRICT_PARAMS", false),
+        "dynamic_models": ModelRegistry::dynamic_models(env),
+        "read_only": config::read_only(env),
+        "system_prompt_set": config::var(env, "SYSTEM_PROMPT").is_some(),
+        "stats_enabled": env.kv("STATS").is_ok(),
+        "default_models": default_models,
+        "warmup": {
+            "models": crate::warmup::models(env),
+            "neurons_used": crate::warmup::neurons_used(),
+        },
+        "features": McpServer::experimental_capabilities(env),
+    })
+}
+
+fn json_contents(uri: &str, value: &serde_json::Value, format: ResourceFormat) -> ResourceContents {
+    let pretty = || serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string());
+
+    match format {
+        ResourceFormat::Json => contents(uri, "application/json", pretty()),
+        ResourceFormat::Compact => contents(uri, "application/json", value.to_string()),
+        ResourceFormat::Markdown => contents(uri, "text/markdown", format!("# {}\n\n```json\n{}\n```\n", uri, pretty())),
+    }
+}
+
+fn contents(uri: &str, mime_type: &str, text: String) -> ResourceContents {
+    ResourceContents {
+        contents: vec![ResourceContent {
+            uri: uri.to_string(),
+            mime_type: mime_type.to_string(),
+            meta: Some(json!({ "etag": etag(&text) })),
+            text,
+        }],
+    }
+}
+
+/// A model as a readable card, with its input schema as a fenced JSON block.
+fn model_card(model: &ModelInfo) -> String {
+    let schema = serde_json::to_string_pretty(&model.input_schema).unwrap_or_default();
+    format!(
+        "# {}\n\n{}\n\n- **Id:** `{}`\n- **Category:** {}\n- **Base cost:** {} neurons\n\n## Input schema\n\n```json\n{}\n```\n",
+        model.name,
+        model.description,
+        model.id,
+        model.category.as_str(),
+        model.base_neurons,
+        schema
+    )
+}
+
+/// Strong ETag for resource text (FNV-1a, 64-bit), stable across requests and deployments.
+pub fn etag(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("\"{:016x}\"", hash)
+}
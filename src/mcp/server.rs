@@ -3,15 +3,40 @@
 
 use worker::*;
 use crate::mcp::protocol::*;
-use crate::mcp::{tools, resources};
-use crate::ai::AiBridge;
+use crate::mcp::{tools, resources, prompts, session};
+use crate::ai::{artifacts, stats, AiBridge, AiError, AiResponse, ModelRegistry, TokenUsage};
+use crate::ai::models::{LengthRoute, ModelCategory, LENGTH_ROUTE_ALIAS};
+use crate::config;
+use crate::tasks;
+use crate::usage;
+use futures_util::{StreamExt, TryStreamExt};
 use serde_json::json;
 
+/// Answered when the client asks for a version this build doesn't know.
+pub const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// Every MCP protocol version `initialize` will agree to, newest first.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Most candidate completions one `tools/call` can ask for with `n`.
+const MAX_COMPLETIONS: u64 = 4;
+
 pub struct McpServer;
 
 impl McpServer {
     /// Returns None for notifications (no response needed), Some for requests.
-    pub async fn handle_request(env: &Env, req: JsonRpcRequest) -> Option<JsonRpcResponse> {
+    /// `session_id` is the caller's `Mcp-Session-Id`, or the newly issued one for
+    /// `initialize`; `caller` is what the session store said about it and where
+    /// the request came from. No backend call is started after `deadline_ms`
+    /// (a Unix time in milliseconds).
+    pub async fn handle_request(
+        env: &Env,
+        ctx: &Context,
+        session_id: Option<&str>,
+        caller: &session::Caller,
+        deadline_ms: u64,
+        req: JsonRpcRequest,
+    ) -> Option<JsonRpcResponse> {
         let method = req.method.as_str();
         let id = req.id.clone();
 
@@ -19,37 +44,90 @@ impl McpServer {
         if id.is_none() || id.as_ref() == Some(&serde_json::Value::Null) {
             match method {
                 "notifications/initialized" | "notifications/cancelled" => {}
+                // This server doesn't use filesystem roots, so there's nothing to refresh
+                "notifications/roots/list_changed" => {}
                 _ => console_log!("Unhandled notification: {}", method),
             }
             return None;
         }
 
+        // ALLOWED_METHODS narrows the surface; the handshake always works
+        let allowed = matches!(method, "initialize" | "ping")
+            || config::allowed_methods(env).is_none_or(|methods| methods.iter().any(|m| m == method));
+        if !allowed {
+            return Some(JsonRpcResponse::error(id, -32601, format!("Method not found: {}", method)));
+        }
+
+        // With STRICT_HANDSHAKE, tools and resources need a completed `initialize` first
+        let needs_handshake = method.starts_with("tools/")
+            || method.starts_with("resources/")
+            || method.starts_with("prompts/");
+        if needs_handshake && config::flag(env, "STRICT_HANDSHAKE", false) && !caller.status.initialized {
+            return Some(JsonRpcResponse::from_error(id, JsonRpcError::invalid_request("Server not initialized")));
+        }
+
         let result = match method {
-            "initialize" => Self::handle_initialize(),
+            "initialize" => Self::handle_initialize(env, session_id, req.params.as_ref()).await,
             "ping" => Ok(json!({})),
-            "tools/list" => Self::handle_tools_list(),
-            "tools/call" => Self::handle_tools_call(env, req.params).await,
+            // Plumbing tests: params come straight back, with no model involved
+            "echo" if config::flag(env, "DEBUG_MODE", false) => Ok(req.params.unwrap_or(serde_json::Value::Null)),
+            "tools/list" => Self::handle_tools_list(env, req.params),
+            "tools/call" => Self::handle_tools_call(env, ctx, session_id, caller, deadline_ms, req.params).await,
             "resources/list" => Self::handle_resources_list(),
-            "resources/read" => Self::handle_resources_read(req.params),
+            "resources/read" => Self::handle_resources_read(env, req.params).await,
+            "resources/readBatch" => Self::handle_resources_read_batch(env, req.params).await,
+            "prompts/list" => Self::handle_prompts_list(env),
+            "prompts/get" => Self::handle_prompts_get(env, req.params),
             _ => return Some(JsonRpcResponse::error(id, -32601, format!("Method not found: {}", method))),
         };
 
         Some(match result {
             Ok(value) => JsonRpcResponse::success(id, value),
-            Err(e) => JsonRpcResponse::error(id, -32603, e),
+            Err(e) => JsonRpcResponse::from_error(id, e),
         })
     }
 
-    fn handle_initialize() -> Result<serde_json::Value, String> {
+    /// Whether `req` is a `tools/call` that will run an audio (transcription) model.
+    pub fn is_audio_tool_call(env: &Env, req: &JsonRpcRequest) -> bool {
+        if req.method != "tools/call" {
+            return false;
+        }
+        let Some(name) = req.params.as_ref().and_then(|p| p.get("name")).and_then(|n| n.as_str()) else {
+            return false;
+        };
+
+        let model_id = match ModelCategory::from_name(name) {
+            Some(category) => ModelRegistry::default_for(env, &category),
+            None => name.to_string(),
+        };
+        ModelRegistry::lookup(env, &model_id).is_some_and(|m| m.category == ModelCategory::Audio)
+    }
+
+    async fn handle_initialize(
+        env: &Env,
+        session_id: Option<&str>,
+        params: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, JsonRpcError> {
+        if let Some(session_id) = session_id {
+            session::mark_initialized(env, session_id, Self::client_wants_structured_content(params)).await;
+        }
+
+        let mut experimental = Self::experimental_capabilities(env);
+        experimental["cloudfree/limits"] = Self::session_limits(env, session_id).await;
+
         Ok(serde_json::json!({
-            "protocolVersion": "2025-03-26",
+            "protocolVersion": Self::negotiate_version(params),
             "capabilities": {
                 "tools": {
                     "listChanged": false
                 },
                 "resources": {
                     "listChanged": false
-                }
+                },
+                "prompts": {
+                    "listChanged": false
+                },
+                "experimental": experimental
             },
             "serverInfo": {
                 "name": "cloudfree-mcp",
@@ -58,42 +136,724 @@ impl McpServer {
         }))
     }
 
-    fn handle_tools_list() -> Result<serde_json::Value, String> {
-        let tools_list = tools::list_tools();
-        serde_json::to_value(tools_list).map_err(|e| e.to_string())
+    /// The limits a new session starts with, so clients can plan without a
+    /// round trip. Limits this deployment doesn't enforce are left out: there is
+    /// no request rate limit, so `rate_limit` never appears. `neuron_budget` is
+    /// `MAX_CALL_NEURONS`, and with `STATS` bound `neurons_used` is the session's
+    /// counter for the day and `neurons_remaining` what's left of the budget.
+    async fn session_limits(env: &Env, session_id: Option<&str>) -> serde_json::Value {
+        let mut limits = json!({
+            "request_budget_ms": tasks::TimeBudget::limit_ms(env),
+            "timeout_ms": config::ai_timeout_ms(env),
+            "max_batch_size": config::max_batch_size(env),
+            "max_completions": MAX_COMPLETIONS,
+            "max_result_bytes": config::max_result_bytes(env),
+        });
+
+        let budget = config::max_call_neurons(env);
+        if budget > 0 {
+            limits["neuron_budget"] = json!(budget);
+        }
+        let client = session_id.map_or_else(|| usage::ANONYMOUS.to_string(), usage::session_label);
+        if let Some(used) = usage::current(env, &client).await {
+            limits["neurons_used"] = json!(used);
+            if budget > 0 {
+                limits["neurons_remaining"] = json!((budget as u64).saturating_sub(used));
+            }
+            limits["usage_resource"] = json!(usage::USAGE_URI);
+        }
+        limits
+    }
+
+    /// Echo the client's requested version when supported, else offer ours.
+    fn negotiate_version(params: Option<&serde_json::Value>) -> &'static str {
+        let requested = params.and_then(|p| p.get("protocolVersion")).and_then(|v| v.as_str());
+        SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .find(|v| Some(**v) == requested)
+            .copied()
+            .unwrap_or(PROTOCOL_VERSION)
     }
 
-    async fn handle_tools_call(env: &Env, params: Option<serde_json::Value>) -> Result<serde_json::Value, String> {
+    /// `structuredContent` arrived in protocol 2025-06-18; older clients can opt in
+    /// with the `cloudfree/structuredContent` experimental capability.
+    fn client_wants_structured_content(params: Option<&serde_json::Value>) -> bool {
+        let Some(params) = params else {
+            return false;
+        };
+        let version_ok = params.get("protocolVersion")
+            .and_then(|v| v.as_str())
+            .is_some_and(|v| v >= "2025-06-18");
+        let opted_in = params.pointer("/capabilities/experimental/cloudfree~1structuredContent").is_some();
+        version_ok || opted_in
+    }
+
+    /// Server-specific extensions, keyed by name so clients can feature-detect them.
+    pub fn experimental_capabilities(env: &Env) -> serde_json::Value {
+        let mut experimental = serde_json::Map::new();
+
+        // Tool results carry `_meta.model_used` and any input adjustments
+        experimental.insert("cloudfree/resultMeta".to_string(), json!({}));
+
+        // `__raw: true` in tool arguments bypasses input formatting
+        experimental.insert("cloudfree/rawPassthrough".to_string(), json!({}));
+
+        experimental.insert("cloudfree/resourcesReadBatch".to_string(), json!({}));
+
+        // `__category` in tool arguments overrides the guessed category of dynamic models
+        experimental.insert("cloudfree/categoryHint".to_string(), json!({}));
+
+        // tools/call with a category name ("llm", "embedding", ...) uses the default model
+        experimental.insert("cloudfree/categoryAliases".to_string(), json!({}));
+
+        if LengthRoute::from_env(env).is_some() {
+            experimental.insert("cloudfree/lengthRouting".to_string(), json!({ "tool": LENGTH_ROUTE_ALIAS }));
+        }
+
+        // tools/list accepts `max_neurons` to hide models that cost more
+        experimental.insert("cloudfree/toolsBudgetFilter".to_string(), json!({}));
+
+        // Opt in from older protocol versions to get `structuredContent` on tool results
+        experimental.insert("cloudfree/structuredContent".to_string(), json!({}));
+
+        experimental.insert("cloudfree/summarize".to_string(), json!({ "tool": "__summarize" }));
+        experimental.insert("cloudfree/translate".to_string(), json!({ "tool": "__translate" }));
+        experimental.insert("cloudfree/capabilities".to_string(), json!({ "tool": "__capabilities" }));
+
+        if let Some(methods) = config::allowed_methods(env) {
+            experimental.insert("cloudfree/allowedMethods".to_string(), json!({ "methods": methods }));
+        }
+        if config::flag(env, "STRICT_PARAMS", false) {
+            experimental.insert("cloudfree/strictParams".to_string(), json!({}));
+        }
+        if config::flag(env, "DEBUG_MODE", false) {
+            experimental.insert("cloudfree/debug".to_string(), json!({ "methods": ["echo"] }));
+        }
+        if config::flag(env, "STRICT_HANDSHAKE", false) {
+            experimental.insert("cloudfree/strictHandshake".to_string(), json!({}));
+        }
+        if config::read_only(env) {
+            experimental.insert("cloudfree/readOnly".to_string(), json!({}));
+        }
+
+        serde_json::Value::Object(experimental)
+    }
+
+    fn handle_tools_list(env: &Env, params: Option<serde_json::Value>) -> Result<serde_json::Value, JsonRpcError> {
+        let params: ListToolsParams = match params {
+            Some(params) => serde_json::from_value(params)
+                .map_err(|e| JsonRpcError::invalid_params(format!("Invalid params: {}", e)))?,
+            None => ListToolsParams::default(),
+        };
+
+        let tools_list = tools::list_tools(env, params.max_neurons);
+        serde_json::to_value(tools_list).map_err(|e| JsonRpcError::internal(e.to_string()))
+    }
+
+    async fn handle_tools_call(
+        env: &Env,
+        ctx: &Context,
+        session_id: Option<&str>,
+        caller: &session::Caller,
+        deadline_ms: u64,
+        params: Option<serde_json::Value>) -> Result<serde_json::Value, JsonRpcError> {
         let params: CallToolParams = serde_json::from_value(params.unwrap_or(json!({})))
-            .map_err(|e| format!("Invalid params: {}", e))?;
+            .map_err(|e| JsonRpcError::invalid_params(format!("Invalid params: {}", e)))?;
+
+        // Before anything walks them, free tools included; calls that run are
+        // checked again with the rest of `check_call`
+        let (max_depth, max_keys) = (config::max_argument_depth(env), config::max_argument_keys(env));
+        for arguments in [&params.arguments, &params.input].into_iter().flatten() {
+            tools::check_argument_complexity(arguments, max_depth, max_keys).map_err(JsonRpcError::invalid_params)?;
+        }
+
+        // Discovery, not inference: answered even in read-only mode and never billed
+        if params.name == "__capabilities" {
+            let tool_result = tools::create_tool_result(resources::capabilities(env), false, false);
+            return serde_json::to_value(tool_result).map_err(|e| JsonRpcError::internal(e.to_string()));
+        }
+
+        // `__estimate` and `__cost` price a call without making it; also free
+        if params.name == "__estimate" || params.name == "__cost" {
+            let arguments = params.arguments.unwrap_or_else(|| json!({}));
+            let model = arguments.get("model")
+                .and_then(|m| m.as_str())
+                .ok_or_else(|| JsonRpcError::invalid_params("Missing 'model' field"))?;
+            let model_id = match ModelCategory::from_name(model) {
+                Some(category) => ModelRegistry::default_for(env, &category),
+                None => model.to_string(),
+            };
+            let input = arguments.get("arguments").cloned().unwrap_or_else(|| json!({}));
+            let breakdown = AiBridge::explain_cost(env, &model_id, input).map_err(|e| match e {
+                AiError::UnknownModel(id) => JsonRpcError::new(-32601, format!("Unknown model: {}", id)),
+                e => JsonRpcError::invalid_params(e.to_string()),
+            })?;
+            // `__estimate` is the short form: just the token split and the total
+            let output = if params.name == "__estimate" {
+                json!({
+                    "model": breakdown["model"],
+                    "prompt_tokens": breakdown["input_tokens"],
+                    "completion_tokens": breakdown["output_tokens"],
+                    "neurons": breakdown["neurons"],
+                })
+            } else {
+                breakdown
+            };
+            let tool_result = tools::create_tool_result(output, false, false);
+            return serde_json::to_value(tool_result).map_err(|e| JsonRpcError::internal(e.to_string()));
+        }
+
+        // Session state only; nothing runs
+        if params.name == "__set_defaults" {
+            let session_id = session_id
+                .ok_or_else(|| JsonRpcError::invalid_params("__set_defaults needs a session (Mcp-Session-Id)"))?;
+            let defaults = match params.arguments.as_ref().and_then(|a| a.get("defaults")) {
+                Some(serde_json::Value::Object(defaults)) => defaults.clone(),
+                None => serde_json::Map::new(),
+                Some(_) => return Err(JsonRpcError::invalid_params("'defaults' must be an object")),
+            };
+            session::set_default_arguments(env, session_id, defaults.clone()).await;
+            let tool_result = tools::create_tool_result(json!({ "defaults": defaults }), false, false);
+            return serde_json::to_value(tool_result).map_err(|e| JsonRpcError::internal(e.to_string()));
+        }
+
+        let budget = tasks::TimeBudget::from_env(env)
+            .until(deadline_ms)
+            .in_session(env, session_id);
+        let mut arguments = match (params.arguments, params.input) {
+            (Some(arguments), _) => arguments,
+            (None, Some(input)) => {
+                console_warn!("tools/call for {} used deprecated 'input'; send 'arguments' instead", params.name);
+                input
+            }
+            (None, None) => json!({}),
+        };
+
+        // `__debug: true` echoes the untouched backend result, but only when DEBUG_MODE is on
+        let debug = arguments.as_object_mut()
+            .and_then(|args| args.remove("__debug"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+            && config::flag(env, "DEBUG_MODE", false);
+
+        if let Some(schema) = tools::input_schema(env, &params.name) {
+            tools::validate_against_schema(&schema, &arguments).map_err(tools::validation_error)?;
+        }
+        tools::check_non_empty_inputs(&arguments).map_err(JsonRpcError::invalid_params)?;
+        let mut completions = 1;
+        let mut base64_embeddings = false;
+        let mut routed = None;
+        let result = if tasks::is_builtin(&params.name) {
+            Self::check_call(env, session_id, caller, &arguments, None).await?;
+            tasks::run(env, &params.name, arguments, &budget).await
+        } else {
+            // A bare category name ("llm", "embedding", ...) runs that category's default model
+            let model_id = match ModelCategory::from_name(&params.name) {
+                Some(category) => ModelRegistry::default_for(env, &category),
+                // `auto` picks a model by prompt length when LENGTH_ROUTES is set
+                None if params.name == LENGTH_ROUTE_ALIAS => match LengthRoute::from_env(env) {
+                    Some(route) => {
+                        let (model_id, tokens) = route.pick(&arguments);
+                        routed = Some(json!({
+                            "alias": LENGTH_ROUTE_ALIAS,
+                            "estimated_tokens": tokens,
+                            "threshold": route.threshold,
+                        }));
+                        model_id.to_string()
+                    }
+                    None => params.name,
+                },
+                None => params.name,
+            };
+
+            let model = ModelRegistry::lookup(env, &model_id);
+
+            // Session defaults fill in whatever the caller left out of an LLM call
+            let is_llm = model.as_ref().is_some_and(|m| m.category == ModelCategory::Llm);
+            if is_llm && !caller.status.default_arguments.is_empty() {
+                if let Some(args) = arguments.as_object_mut() {
+                    for (key, value) in &caller.status.default_arguments {
+                        args.entry(key.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+                if let Some(schema) = tools::input_schema(env, &model_id) {
+                    tools::validate_against_schema(&schema, &arguments).map_err(tools::validation_error)?;
+                }
+            }
+
+            // `n` asks an LLM for several candidate completions
+            if let Some(n) = arguments.as_object_mut().filter(|_| is_llm).and_then(|args| args.remove("n")) {
+                completions = n.as_u64()
+                    .filter(|n| (1..=MAX_COMPLETIONS).contains(n))
+                    .ok_or_else(|| JsonRpcError::invalid_params(format!("'n' must be between 1 and {}", MAX_COMPLETIONS)))?;
+            }
+            Self::check_call(env, session_id, caller, &arguments, Some((&model_id, completions))).await?;
+
+            // `encoding: "base64"` packs embedding vectors as little-endian float32
+            let is_embedding = model.as_ref().is_some_and(|m| m.category == ModelCategory::Embedding);
+            if let Some(encoding) = arguments.as_object_mut().filter(|_| is_embedding).and_then(|args| args.remove("encoding")) {
+                base64_embeddings = match encoding.as_str() {
+                    Some("float") => false,
+                    Some("base64") => true,
+                    _ => return Err(JsonRpcError::invalid_params("'encoding' must be one of: \"float\", \"base64\"")),
+                };
+            }
 
-        let result = AiBridge::run_inference(env, &params.name, params.arguments.unwrap_or(json!({})))
-            .await
-            .map_err(|e| format!("AI inference failed: {}", e))?;
+            // Embedding batches bigger than the backend takes are split up
+            let texts = arguments.get("text").and_then(|t| t.as_array()).map_or(0, Vec::len);
+            let oversized_batch = model.as_ref()
+                .filter(|m| m.category == ModelCategory::Embedding)
+                .and_then(|m| m.max_batch)
+                .filter(|max_batch| texts > *max_batch);
+
+            if completions > 1 {
+                Self::run_completions(env, &model_id, arguments, completions, &budget).await
+            } else if let Some(max_batch) = oversized_batch {
+                Self::run_embedding_batches(env, &model_id, arguments, max_batch, &budget).await
+            } else {
+                let chain = model.as_ref().map(|m| ModelRegistry::fallback_chain(env, m)).unwrap_or_default();
+                Self::run_with_fallback(env, &model_id, arguments, chain, &budget).await
+            }
+        };
+
+        // Execution failures are tool results with isError so the model can see
+        // them; JSON-RPC errors are reserved for malformed requests, which
+        // includes URL arguments that are refused or can't be fetched
+        let result = match result {
+            Ok(result) => result,
+            Err(AiError::UnknownModel(id)) => {
+                return Err(JsonRpcError::new(-32601, format!("Unknown model: {}", id)));
+            }
+            Err(e @ AiError::InvalidUrl(_)) => return Err(JsonRpcError::invalid_params(e.to_string())),
+            Err(AiError::Unavailable(msg)) => return Err(JsonRpcError::internal(msg)),
+            Err(e) => {
+                let message = match e {
+                    AiError::InvalidInput(msg) | AiError::InvalidUrl(msg) => format!("Invalid input: {}", msg),
+                    AiError::Backend(msg) => format!("AI inference failed: {}", msg),
+                    AiError::UnknownModel(id) => format!("Unknown model: {}", id),
+                    AiError::Unavailable(msg) => msg,
+                };
+                let tool_result = tools::create_tool_result(json!(message), true, false);
+                return serde_json::to_value(tool_result).map_err(|e| JsonRpcError::internal(e.to_string()));
+            }
+        };
+
+        // Fire-and-forget: record how far off the estimate was, and who used what
+        let actual = result.neurons_reported.then_some(result.neurons_used);
+        ctx.wait_until(stats::record(env.clone(), result.model_used.clone(), result.estimated_neurons, actual));
+        ctx.wait_until(usage::record(env.clone(), caller.usage_client(session_id), result.neurons_used));
+
+        let mut meta = result.meta;
+        if let Some(routed) = routed {
+            meta.insert("routed".to_string(), routed);
+        }
+        if debug {
+            meta.insert("raw".to_string(), result.result.clone());
+        }
+        let category = result.category;
+        let mut output = match category {
+            Some(ModelCategory::Embedding) if base64_embeddings => {
+                let (output, dimensions) = tools::base64_embeddings_result(result.result);
+                meta.insert("encoding".to_string(), json!({
+                    "format": "base64",
+                    "dtype": "float32",
+                    "byte_order": "little",
+                    "dimensions": dimensions,
+                }));
+                output
+            }
+            Some(ModelCategory::Embedding) => tools::embeddings_result(result.result),
+            _ => result.result,
+        };
+
+        // Remove chat-template residue from LLM text (STRIP_ARTIFACTS); `__debug`
+        // still shows the raw output
+        if let Some(extra) = artifacts::from_env(env).filter(|_| category == Some(ModelCategory::Llm)) {
+            let stripped = if completions > 1 {
+                output.as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .fold(false, |stripped, candidate| artifacts::strip_result(candidate, &extra) || stripped)
+            } else {
+                artifacts::strip_result(&mut output, &extra)
+            };
+            if stripped {
+                meta.insert("artifacts_stripped".to_string(), json!(true));
+            }
+        }
+
+        // Cap over-long text output (MAX_OUTPUT_CHARS, 0 = unlimited)
+        let max_output_chars = config::number(env, "MAX_OUTPUT_CHARS", 0usize);
+        if max_output_chars > 0 && (category == Some(ModelCategory::Llm) || output.is_string()) {
+            let cut = if completions > 1 {
+                output.as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|candidate| tools::truncate_text(candidate, max_output_chars))
+                    .max()
+            } else {
+                tools::truncate_text(&mut output, max_output_chars)
+            };
+            if let Some(original_chars) = cut {
+                meta.insert("truncated".to_string(), json!({
+                    "original_chars": original_chars,
+                    "max_chars": max_output_chars,
+                }));
+            }
+        }
+
+        let billed_neurons = config::billed_neurons(env, result.neurons_used);
+
+        let structured = caller.status.structured_content
+            .then(|| tools::structured_content(&output))
+            .flatten();
 
         // Include neurons used in the response
-        let mut tool_result = tools::create_tool_result(result.result, false);
+        let compact = Self::compact_output(env, category.as_ref());
+        let mut tool_result = if completions > 1 {
+            tools::create_candidates_result(output, compact)
+        } else {
+            tools::create_tool_result(output, false, compact)
+        };
+        tool_result.structured_content = structured;
+
+        // Bound how many blocks a client has to take (MAX_CONTENT_BLOCKS, 0 = unlimited)
+        let max_blocks = config::max_content_blocks(env);
+        let total_blocks = tool_result.content.len();
+        if max_blocks > 0 && total_blocks > max_blocks {
+            tool_result.content.truncate(max_blocks);
+            meta.insert("content_blocks_truncated".to_string(), json!({
+                "total": total_blocks,
+                "returned": max_blocks,
+            }));
+        }
 
         // Add neurons info to the text response
-        if let Some(ContentBlock::Text { text }) = tool_result.content.first_mut() {
-            *text = format!("{}\n\n[Neurons used: {}]", text, result.neurons_used);
+        if let Some(ContentBlock::Text { text }) = tool_result.content.last_mut() {
+            *text = format!("{}\n\n[Neurons used: {}]", text, billed_neurons);
+        }
+
+        meta.insert("model_used".to_string(), json!(result.model_used));
+        meta.insert("elapsed_ms".to_string(), json!(budget.elapsed_ms()));
+        if let Some(usage) = result.usage {
+            meta.insert("usage".to_string(), json!({
+                "prompt_tokens": usage.prompt_tokens,
+                "completion_tokens": usage.completion_tokens,
+                "total_tokens": usage.total_tokens(),
+                "neurons": billed_neurons,
+            }));
+        }
+        tool_result.meta = Some(serde_json::Value::Object(meta));
+
+        // Refuse to ship a result too big for clients to handle (MAX_RESULT_BYTES).
+        // The inference already happened, so the neurons above are still counted
+        let max_result_bytes = config::max_result_bytes(env);
+        let result_bytes = serde_json::to_vec(&tool_result).map_or(0, |bytes| bytes.len());
+        if max_result_bytes > 0 && result_bytes > max_result_bytes {
+            let message = format!(
+                "Result is {} bytes, over the {} byte limit; split the input into smaller calls",
+                result_bytes, max_result_bytes
+            );
+            let mut tool_result = tools::create_tool_result(json!(message), true, false);
+            tool_result.meta = Some(json!({
+                "model_used": result.model_used,
+                "result_bytes": result_bytes,
+                "max_result_bytes": max_result_bytes,
+                "usage": { "neurons": billed_neurons },
+            }));
+            return serde_json::to_value(tool_result).map_err(|e| JsonRpcError::internal(e.to_string()));
+        }
+
+        serde_json::to_value(tool_result).map_err(|e| JsonRpcError::internal(e.to_string()))
+    }
+
+    /// The checks every call goes through before anything runs, whether it came
+    /// in as a `tools/call` or as `POST /infer`: read-only mode, a closed session,
+    /// argument complexity, and for `model` (an id and how many times it will run)
+    /// the non-curated model cap and the estimated cost against `MAX_CALL_NEURONS`.
+    pub async fn check_call(
+        env: &Env,
+        session_id: Option<&str>,
+        caller: &session::Caller,
+        arguments: &serde_json::Value,
+        model: Option<(&str, u64)>,
+    ) -> Result<(), JsonRpcError> {
+        if config::read_only(env) {
+            return Err(JsonRpcError::internal("Server is in read-only mode"));
+        }
+        if caller.status.closed {
+            return Err(JsonRpcError::new(-32800, "Request cancelled: session closed"));
+        }
+        tools::check_argument_complexity(arguments, config::max_argument_depth(env), config::max_argument_keys(env))
+            .map_err(JsonRpcError::invalid_params)?;
+
+        let Some((model_id, calls)) = model else {
+            return Ok(());
+        };
+
+        // Cap how many made-up ids one caller can try (MAX_DYNAMIC_MODELS)
+        let max_dynamic = config::max_dynamic_models(env);
+        if max_dynamic > 0 && !ModelRegistry::is_curated(model_id) {
+            let key = caller.dynamic_models_key(session_id);
+            if !session::note_dynamic_model(env, &key, model_id, max_dynamic).await {
+                return Err(JsonRpcError::new(-32601, format!(
+                    "Unknown model: {} (this caller has reached its limit of {} non-curated models)",
+                    model_id, max_dynamic
+                )));
+            }
+        }
+
+        let max_neurons = config::max_call_neurons(env);
+        let estimated = ModelRegistry::lookup(env, model_id).map_or(0, |m| m.estimate_neurons(arguments));
+        let total = (estimated as u64).saturating_mul(calls);
+        if max_neurons > 0 && total > max_neurons as u64 {
+            let what = if calls > 1 { format!("{} completions are", calls) } else { "This call is".to_string() };
+            return Err(JsonRpcError::invalid_params(format!(
+                "{} estimated at {} neurons, over the {} neuron limit (MAX_CALL_NEURONS)",
+                what, total, max_neurons
+            )));
+        }
+        Ok(())
+    }
+
+    /// Run the call on `model_id`, and on each model of `chain` in turn while the
+    /// backend keeps failing (each tier with its own retries). Bad input isn't
+    /// retried elsewhere, and neither is an open circuit breaker, which covers
+    /// every model. A failed tier may still have been billed, so it counts at its
+    /// estimate in the total; `_meta.fallback` says which tier answered and why
+    /// the others failed. No tier is started once `budget` has run out or the
+    /// session has been closed.
+    async fn run_with_fallback(
+        env: &Env,
+        model_id: &str,
+        arguments: serde_json::Value,
+        chain: Vec<String>,
+        budget: &tasks::TimeBudget,
+    ) -> Result<AiResponse, AiError> {
+        let deadline_ms = budget.deadline_ms();
+        if chain.is_empty() {
+            return budget.track(AiBridge::run_inference(env, model_id, arguments, Some(deadline_ms))).await;
+        }
+
+        let mut failed = Vec::new();
+        let mut failed_neurons = 0u32;
+        let mut current = model_id.to_string();
+        let mut remaining = chain.into_iter();
+        loop {
+            match budget.track(AiBridge::run_inference(env, &current, arguments.clone(), Some(deadline_ms))).await {
+                Ok(mut response) => {
+                    if !failed.is_empty() {
+                        response.neurons_used = response.neurons_used.saturating_add(failed_neurons);
+                        response.estimated_neurons = response.estimated_neurons.saturating_add(failed_neurons);
+                        // Part of the total is an estimate now
+                        response.neurons_reported = false;
+                        response.meta.insert("fallback".to_string(), json!({
+                            "requested": model_id,
+                            "tier": failed.len(),
+                            "failed": failed,
+                        }));
+                    }
+                    return Ok(response);
+                }
+                Err(AiError::Backend(message)) => {
+                    let Some(next) = remaining.next() else {
+                        return Err(AiError::Backend(message));
+                    };
+                    if Date::now().as_millis() >= deadline_ms {
+                        console_warn!("{} failed ({}); no time left to fall back to {}", current, message, next);
+                        return Err(AiError::Backend(message));
+                    }
+                    console_warn!("{} failed ({}); falling back to {}", current, message, next);
+                    let neurons = ModelRegistry::lookup(env, &current).map_or(0, |m| m.estimate_neurons(&arguments));
+                    failed_neurons = failed_neurons.saturating_add(neurons);
+                    failed.push(json!({ "model": current, "error": message, "estimated_neurons": neurons }));
+                    current = next;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Run `n` completions of the same LLM call concurrently (up to
+    /// `BATCH_CONCURRENCY` at a time), each with its own seed (consecutive from
+    /// the caller's `seed` if given). The result is an array with one entry per
+    /// completion that succeeded; cost and usage are the totals, and
+    /// `_meta.failed_completions` lists the ones that didn't.
+    async fn run_completions(
+        env: &Env,
+        model_id: &str,
+        arguments: serde_json::Value,
+        n: u64,
+        budget: &tasks::TimeBudget,
+    ) -> Result<AiResponse, AiError> {
+        let base_seed = arguments.get("seed")
+            .and_then(|s| s.as_u64())
+            .unwrap_or_else(|| (js_sys::Math::random() * u32::MAX as f64) as u64);
+
+        let calls = (0..n).map(|i| {
+            let mut input = arguments.clone();
+            input["seed"] = json!(base_seed.wrapping_add(i));
+            budget.track(AiBridge::run_inference(env, model_id, input, Some(budget.deadline_ms())))
+        });
+        let results: Vec<Result<AiResponse, AiError>> = futures_util::stream::iter(calls)
+            .buffered(config::batch_concurrency(env))
+            .collect()
+            .await;
+
+        // Completions that succeeded were paid for, so one failure doesn't discard them
+        let mut responses = Vec::with_capacity(results.len());
+        let mut failures = Vec::new();
+        let mut first_error = None;
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(response) => responses.push(response),
+                Err(e) => {
+                    failures.push(json!({ "index": index, "error": e.to_string() }));
+                    if first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                }
+            }
+        }
+        if responses.is_empty() {
+            if let Some(e) = first_error {
+                return Err(e);
+            }
+        }
+
+        let succeeded = responses.len();
+        let mut combined = Self::combine_responses(model_id, responses);
+        combined.meta.insert("completions".to_string(), json!(succeeded));
+        if !failures.is_empty() {
+            combined.meta.insert("requested_completions".to_string(), json!(n));
+            combined.meta.insert("failed_completions".to_string(), json!(failures));
+        }
+        Ok(combined)
+    }
+
+    /// Embed a `text` array larger than the model's `max_batch` in backend-sized
+    /// calls run concurrently (up to `BATCH_CONCURRENCY` at a time), then put the vectors back together in input order.
+    async fn run_embedding_batches(
+        env: &Env,
+        model_id: &str,
+        mut arguments: serde_json::Value,
+        max_batch: usize,
+        budget: &tasks::TimeBudget,
+    ) -> Result<AiResponse, AiError> {
+        let texts = match arguments.as_object_mut().and_then(|args| args.remove("text")) {
+            Some(serde_json::Value::Array(texts)) => texts,
+            _ => return Err(AiError::InvalidInput("'text' must be an array".to_string())),
+        };
+
+        // At most BATCH_CONCURRENCY calls in flight; `buffered` yields results in
+        // input order whatever order they finish in
+        let calls = texts.chunks(max_batch).map(|batch| {
+            let mut input = arguments.clone();
+            input["text"] = json!(batch);
+            budget.track(AiBridge::run_inference(env, model_id, input, Some(budget.deadline_ms())))
+        });
+        let responses: Vec<AiResponse> = futures_util::stream::iter(calls)
+            .buffered(config::batch_concurrency(env))
+            .try_collect()
+            .await?;
+        let batches = responses.len();
+
+        let mut combined = Self::combine_responses(model_id, responses);
+        let mut data = Vec::with_capacity(texts.len());
+        for result in combined.result.as_array().into_iter().flatten() {
+            let vectors = result.get("data")
+                .and_then(|d| d.as_array())
+                .ok_or_else(|| AiError::Backend(format!("{} returned no embeddings", model_id)))?;
+            data.extend(vectors.iter().cloned());
         }
+        let dimensions = data.first().and_then(|v| v.as_array()).map_or(0, Vec::len);
 
-        serde_json::to_value(tool_result).map_err(|e| e.to_string())
+        combined.result = json!({ "shape": [data.len(), dimensions], "data": data });
+        combined.meta.insert("batches".to_string(), json!(batches));
+        Ok(combined)
     }
 
-    fn handle_resources_list() -> Result<serde_json::Value, String> {
+    /// Several concurrent calls to one model as a single response: the results
+    /// as an array in call order, with neurons and token usage summed.
+    fn combine_responses(model_id: &str, responses: Vec<AiResponse>) -> AiResponse {
+        let mut usage = TokenUsage::default();
+        let mut latency_ms = 0;
+        for response in &responses {
+            usage.add(&response.usage.unwrap_or_default());
+            latency_ms = latency_ms.max(tasks::latency_ms(response));
+        }
+
+        let mut meta = serde_json::Map::new();
+        meta.insert("latency_ms".to_string(), json!(latency_ms));
+
+        AiResponse {
+            neurons_used: responses.iter().map(|r| r.neurons_used).sum(),
+            estimated_neurons: responses.iter().map(|r| r.estimated_neurons).sum(),
+            neurons_reported: responses.iter().all(|r| r.neurons_reported),
+            model_used: model_id.to_string(),
+            category: responses.first().and_then(|r| r.category.clone()),
+            usage: Some(usage),
+            result: responses.into_iter().map(|r| r.result).collect(),
+            meta,
+        }
+    }
+
+    /// Whether JSON results are rendered compactly: `COMPACT_OUTPUT_<CATEGORY>` if set,
+    /// else `COMPACT_OUTPUT`, else compact only for embeddings (machine-consumed vectors).
+    fn compact_output(env: &Env, category: Option<&ModelCategory>) -> bool {
+        if let Some(category) = category {
+            let var = format!("COMPACT_OUTPUT_{}", category.as_str().to_ascii_uppercase());
+            if config::var(env, &var).is_some() {
+                return config::flag(env, &var, false);
+            }
+        }
+        config::flag(env, "COMPACT_OUTPUT", category == Some(&ModelCategory::Embedding))
+    }
+
+    fn handle_resources_list() -> Result<serde_json::Value, JsonRpcError> {
         let resources_list = resources::list_resources();
-        serde_json::to_value(resources_list).map_err(|e| e.to_string())
+        serde_json::to_value(resources_list).map_err(|e| JsonRpcError::internal(e.to_string()))
     }
 
-    fn handle_resources_read(params: Option<serde_json::Value>) -> Result<serde_json::Value, String> {
+    async fn handle_resources_read(env: &Env, params: Option<serde_json::Value>) -> Result<serde_json::Value, JsonRpcError> {
         let params: ReadResourceParams = serde_json::from_value(params.unwrap_or(json!({})))
-            .map_err(|e| format!("Invalid params: {}", e))?;
+            .map_err(|e| JsonRpcError::invalid_params(format!("Invalid params: {}", e)))?;
 
-        let contents = resources::get_resource_content(&params.uri)
-            .ok_or_else(|| format!("Resource not found: {}", params.uri))?;
+        let contents = resources::read_resource(env, &params.uri, params.format).await
+            .ok_or_else(|| JsonRpcError::internal(format!("Resource not found: {}", params.uri)))?;
+
+        serde_json::to_value(contents).map_err(|e| JsonRpcError::internal(e.to_string()))
+    }
+
+    fn handle_prompts_list(env: &Env) -> Result<serde_json::Value, JsonRpcError> {
+        serde_json::to_value(prompts::list_prompts(env)).map_err(|e| JsonRpcError::internal(e.to_string()))
+    }
+
+    fn handle_prompts_get(env: &Env, params: Option<serde_json::Value>) -> Result<serde_json::Value, JsonRpcError> {
+        let params: GetPromptParams = serde_json::from_value(params.unwrap_or(json!({})))
+            .map_err(|e| JsonRpcError::invalid_params(format!("Invalid params: {}", e)))?;
+
+        let result = prompts::get_prompt(env, params)?;
+        serde_json::to_value(result).map_err(|e| JsonRpcError::internal(e.to_string()))
+    }
+
+    /// Non-standard: read several resources in one round trip. Unknown URIs get a
+    /// per-item error instead of failing the whole batch.
+    async fn handle_resources_read_batch(env: &Env, params: Option<serde_json::Value>) -> Result<serde_json::Value, JsonRpcError> {
+        let params: ReadResourceBatchParams = serde_json::from_value(params.unwrap_or(json!({})))
+            .map_err(|e| JsonRpcError::invalid_params(format!("Invalid params: {}", e)))?;
+
+        let mut results = Vec::with_capacity(params.uris.len());
+        for uri in &params.uris {
+            results.push(match resources::read_resource(env, uri, params.format).await {
+                Some(contents) => json!({ "uri": uri, "contents": contents.contents }),
+                None => json!({
+                    "uri": uri,
+                    "error": { "code": -32002, "message": format!("Resource not found: {}", uri) }
+                }),
+            });
+        }
 
-        serde_json::to_value(contents).map_err(|e| e.to_string())
+        Ok(json!({ "results": results }))
     }
 }
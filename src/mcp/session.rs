@@ -0,0 +1,327 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Per-session state keyed by the `Mcp-Session-Id` header.
+//!
+//! Most state lives in isolate memory, so it is best-effort: a session whose
+//! requests land on different isolates sees separate state, and everything is
+//! lost when the isolate is evicted. Whether a session has completed
+//! `initialize`, what the client said it supports, its `__set_defaults`
+//! arguments, and whether it has been closed also go to a Durable Object bound as `SESSIONS`, one per session, so
+//! they hold across isolates. Each request reads them once, as a [`Status`].
+//! The same objects count the non-curated models a caller has tried
+//! (`MAX_DYNAMIC_MODELS`), keyed by session or, for callers without an
+//! initialized session, by client IP. Without the binding all of this falls
+//! back to isolate memory too.
+//!
+//! Closing a session aborts the requests this isolate is running for it, and
+//! requests on other isolates refuse to start another backend call once they
+//! see the closed flag. Neither stops an `AI.run` already under way: no abort
+//! signal is passed to the binding, so that call finishes in the background
+//! and may still be billed.
+
+use futures_util::future::AbortHandle;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use worker::*;
+use crate::usage;
+
+/// Sessions untouched for this long are dropped on the next access.
+const SESSION_IDLE_MS: u64 = 60 * 60 * 1000;
+
+const BINDING: &str = "SESSIONS";
+
+/// How long the shared record of a session is kept after its last change.
+const SHARED_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// The part of a session every isolate needs to agree on.
+#[durable_object]
+pub struct SessionStore {
+    state: State,
+}
+
+impl DurableObject for SessionStore {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let storage = self.state.storage();
+        let flag = |key: &'static str| {
+            let storage = self.state.storage();
+            async move { storage.get::<bool>(key).await.ok().flatten().unwrap_or(false) }
+        };
+
+        match req.path().as_str() {
+            "/status" => Response::from_json(&serde_json::json!({
+                "initialized": flag("initialized").await,
+                "closed": flag("closed").await,
+                "structured_content": flag("structured_content").await,
+                "default_arguments": storage
+                    .get::<serde_json::Map<String, serde_json::Value>>("default_arguments")
+                    .await
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default(),
+            })),
+            "/initialize" => {
+                let client: serde_json::Value = req.json().await?;
+                let structured_content = client.get("structured_content").and_then(|v| v.as_bool()).unwrap_or(false);
+                storage.put("initialized", true).await?;
+                storage.put("structured_content", structured_content).await?;
+                storage.set_alarm(SHARED_TTL).await?;
+                Response::from_json(&serde_json::json!({}))
+            }
+            "/defaults" => {
+                let defaults: serde_json::Map<String, serde_json::Value> = req.json().await?;
+                storage.put("default_arguments", defaults).await?;
+                storage.set_alarm(SHARED_TTL).await?;
+                Response::from_json(&serde_json::json!({}))
+            }
+            "/dynamic" => {
+                let call: serde_json::Value = req.json().await?;
+                let model = call.get("model").and_then(|v| v.as_str()).unwrap_or_default();
+                let max = call.get("max").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+                let mut models: HashSet<String> = storage.get("dynamic_models").await.ok().flatten().unwrap_or_default();
+                let seen = models.len();
+                let allowed = admit(&mut models, model, max);
+                if models.len() > seen {
+                    storage.put("dynamic_models", models).await?;
+                    storage.set_alarm(SHARED_TTL).await?;
+                }
+                Response::from_json(&serde_json::json!({ "allowed": allowed }))
+            }
+            "/close" => {
+                let known = flag("initialized").await && !flag("closed").await;
+                if known {
+                    storage.put("closed", true).await?;
+                    storage.set_alarm(SHARED_TTL).await?;
+                }
+                Response::from_json(&serde_json::json!({ "closed": known }))
+            }
+            _ => Response::error("Not found", 404),
+        }
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        self.state.storage().delete_all().await?;
+        Response::empty()
+    }
+}
+
+/// Ask the session's shared record, posting `body` if there is one; `None`
+/// without the binding or if it can't be reached, in which case callers go by
+/// isolate memory alone.
+async fn shared(env: &Env, session_id: &str, path: &str, body: Option<serde_json::Value>) -> Option<serde_json::Value> {
+    let namespace = env.durable_object(BINDING).ok()?;
+    let answer = async {
+        let stub = namespace.id_from_name(session_id)?.get_stub()?;
+        let mut init = RequestInit::new();
+        if let Some(body) = body {
+            init.with_method(Method::Post).with_body(Some(body.to_string().into()));
+        }
+        let request = Request::new_with_init(&format!("https://session{}", path), &init)?;
+        let mut response = stub.fetch_with_request(request).await?;
+        response.json::<serde_json::Value>().await
+    }
+    .await;
+
+    answer
+        .map_err(|e| console_warn!("Session store unreachable for {}: {}", path, e))
+        .ok()
+}
+
+/// What a request knows about its session, read once when it arrives.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Status {
+    /// The session completed the `initialize` handshake.
+    pub initialized: bool,
+    /// `DELETE /mcp` closed the session.
+    pub closed: bool,
+    /// The client can consume `structuredContent` on tool results.
+    pub structured_content: bool,
+    /// Merged under the arguments of this session's LLM calls (`__set_defaults`).
+    pub default_arguments: serde_json::Map<String, serde_json::Value>,
+}
+
+/// One HTTP request's caller, worked out once when it arrives.
+#[derive(Default)]
+pub struct Caller {
+    /// What the session store said about the `Mcp-Session-Id`, if one was sent.
+    pub status: Status,
+    /// From `CLIENT_IP_HEADER`.
+    pub ip: Option<String>,
+}
+
+impl Caller {
+    /// Who the caller's non-curated model ids count against: `session_id` once
+    /// it has completed `initialize`, otherwise the client IP, so leaving out or
+    /// making up a session id doesn't start a fresh count.
+    pub fn dynamic_models_key(&self, session_id: Option<&str>) -> String {
+        match session_id {
+            Some(session_id) if self.status.initialized => session_id.to_string(),
+            _ => format!("ip:{}", self.ip.as_deref().unwrap_or("unknown")),
+        }
+    }
+
+    /// Who the caller's neurons are recorded under in `stats://usage`: a hash of
+    /// `session_id` once it has completed `initialize`, else nobody in particular.
+    pub fn usage_client(&self, session_id: Option<&str>) -> String {
+        match session_id {
+            Some(session_id) if self.status.initialized => usage::session_label(session_id),
+            _ => usage::ANONYMOUS.to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SessionState {
+    /// Requests that haven't been answered yet, keyed by JSON-encoded id.
+    in_flight: HashMap<String, AbortHandle>,
+    /// Set once `initialize` has been answered for this session.
+    initialized: bool,
+    /// The client can consume `structuredContent` on tool results.
+    structured_content: bool,
+    /// Distinct non-curated model ids this session (or IP) has called.
+    dynamic_models: HashSet<String>,
+    /// Merged under the arguments of this session's LLM calls (`__set_defaults`).
+    default_arguments: serde_json::Map<String, serde_json::Value>,
+    last_seen: u64,
+}
+
+thread_local! {
+    static SESSIONS: RefCell<HashMap<String, SessionState>> = RefCell::new(HashMap::new());
+}
+
+/// A fresh random session id for the `initialize` response.
+pub fn new_session_id() -> String {
+    let mut bytes = [0u8; 16];
+    let _ = getrandom::getrandom(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn with_session<T>(session_id: &str, f: impl FnOnce(&mut SessionState) -> T) -> T {
+    let now = Date::now().as_millis();
+    SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        sessions.retain(|_, s| !s.in_flight.is_empty() || now.saturating_sub(s.last_seen) < SESSION_IDLE_MS);
+
+        let session = sessions.entry(session_id.to_string()).or_default();
+        session.last_seen = now;
+        f(session)
+    })
+}
+
+/// Mark a request id as in flight; `handle` aborts it if the session is closed.
+/// Returns `false` if the id is already in use within this session, which the
+/// MCP spec forbids.
+pub fn begin_request(session_id: &str, request_id: &serde_json::Value, handle: AbortHandle) -> bool {
+    let key = request_id.to_string();
+    with_session(session_id, |s| {
+        if s.in_flight.contains_key(&key) {
+            return false;
+        }
+        s.in_flight.insert(key, handle);
+        true
+    })
+}
+
+/// Release a request id once its response has been produced.
+pub fn end_request(session_id: &str, request_id: &serde_json::Value) {
+    let key = request_id.to_string();
+    SESSIONS.with(|sessions| {
+        // The session may already be gone if it was closed mid-request
+        if let Some(session) = sessions.borrow_mut().get_mut(session_id) {
+            session.in_flight.remove(&key);
+        }
+    });
+}
+
+/// Abort everything this isolate has in flight for the session and forget it.
+fn forget(session_id: &str) -> bool {
+    let Some(session) = SESSIONS.with(|sessions| sessions.borrow_mut().remove(session_id)) else {
+        return false;
+    };
+    for handle in session.in_flight.values() {
+        handle.abort();
+    }
+    true
+}
+
+/// Close the session for the whole deployment, aborting whatever this isolate
+/// has in flight for it. Returns `false` if the session isn't known (or was
+/// already closed).
+pub async fn close(env: &Env, session_id: &str) -> bool {
+    let known_here = forget(session_id);
+    match shared(env, session_id, "/close", None).await {
+        Some(answer) => answer.get("closed").and_then(|v| v.as_bool()).unwrap_or(false) || known_here,
+        None => known_here,
+    }
+}
+
+/// The session as the whole deployment sees it, or as this isolate does without
+/// the session store. Requests still running here for a session that turns out
+/// to be closed are aborted.
+pub async fn status(env: &Env, session_id: &str) -> Status {
+    let local = with_session(session_id, |s| Status {
+        initialized: s.initialized,
+        closed: false,
+        structured_content: s.structured_content,
+        default_arguments: s.default_arguments.clone(),
+    });
+    let Some(mut status) = shared(env, session_id, "/status", None)
+        .await
+        .and_then(|answer| serde_json::from_value::<Status>(answer).ok())
+    else {
+        return local;
+    };
+    // Initialized here even if the write to the store didn't land
+    status.initialized |= local.initialized;
+    if status.closed {
+        forget(session_id);
+    }
+    status
+}
+
+/// Record that the session completed the `initialize` handshake, and what the
+/// client said it supports.
+pub async fn mark_initialized(env: &Env, session_id: &str, structured_content: bool) {
+    with_session(session_id, |s| {
+        s.initialized = true;
+        s.structured_content = structured_content;
+    });
+    let client = serde_json::json!({ "structured_content": structured_content });
+    shared(env, session_id, "/initialize", Some(client)).await;
+}
+
+/// Replace the session's default LLM arguments; an empty map clears them.
+pub async fn set_default_arguments(env: &Env, session_id: &str, defaults: serde_json::Map<String, serde_json::Value>) {
+    with_session(session_id, |s| s.default_arguments = defaults.clone());
+    shared(env, session_id, "/defaults", Some(serde_json::Value::Object(defaults))).await;
+}
+
+/// Count a call to the non-curated model `id` against `key` (see
+/// [`Caller::dynamic_models_key`]). Returns `false`, without recording it, if
+/// it would be the `max + 1`th distinct one.
+pub async fn note_dynamic_model(env: &Env, key: &str, id: &str, max: usize) -> bool {
+    let call = serde_json::json!({ "model": id, "max": max });
+    if let Some(answer) = shared(env, key, "/dynamic", Some(call)).await {
+        return answer.get("allowed").and_then(|v| v.as_bool()).unwrap_or(false);
+    }
+    with_session(key, |s| admit(&mut s.dynamic_models, id, max))
+}
+
+/// Add `id` to `models` unless that would make more than `max`; ids already
+/// there are always allowed.
+fn admit(models: &mut HashSet<String>, id: &str, max: usize) -> bool {
+    if models.contains(id) {
+        return true;
+    }
+    if models.len() >= max {
+        return false;
+    }
+    models.insert(id.to_string());
+    true
+}
@@ -1,13 +1,21 @@
 // Copyright (C) 2026 Jade
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::ai::ModelRegistry;
+use worker::Env;
+use crate::ai::{media, ModelRegistry};
 use crate::mcp::protocol::*;
+use serde::Serialize;
+use serde_json::json;
 
-pub fn list_tools() -> ToolsList {
-    let models = ModelRegistry::get_all_models();
-    let tools = models
+/// All tools, leaving out models whose base cost exceeds `max_neurons` when given.
+/// Built-in tools are always listed since their cost depends on the input.
+/// Models come first in `ModelRegistry::sort_for_listing` order, then the built-ins.
+pub fn list_tools(env: &Env, max_neurons: Option<u32>) -> ToolsList {
+    let mut models = ModelRegistry::get_all_models_for(env);
+    ModelRegistry::sort_for_listing(&mut models);
+    let mut tools: Vec<Tool> = models
         .into_iter()
+        .filter(|model| max_neurons.is_none_or(|max| model.base_neurons <= max))
         .map(|model| Tool {
             name: model.id.clone(),
             description: format!("{} - {}", model.name, model.description),
@@ -15,18 +23,521 @@ pub fn list_tools() -> ToolsList {
         })
         .collect();
 
+    tools.extend(builtin_tools());
+
     ToolsList { tools }
 }
 
-pub fn create_tool_result(result: serde_json::Value, is_error: bool) -> ToolResult {
+/// The input schema of a tool: a built-in, or the model it names (with any
+/// `SCHEMA_OVERRIDES` applied).
+pub fn input_schema(env: &Env, name: &str) -> Option<serde_json::Value> {
+    if let Some(tool) = builtin_tools().into_iter().find(|t| t.name == name) {
+        return Some(tool.input_schema);
+    }
+    ModelRegistry::input_schema_for(env, name)
+}
+
+/// One problem with a tool's arguments, as listed in `data.validation_errors`.
+#[derive(Debug, Serialize)]
+pub struct SchemaViolation {
+    pub field: String,
+    /// `required`, `type`, `enum`, `minimum` or `maximum`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Check arguments against a tool's schema and report every problem at once:
+/// missing `required` fields, top-level values of the wrong `type`, values
+/// outside an `enum`, and numbers outside `minimum`/`maximum`.
+///
+/// The schemas describe the common form of each input, so a few others are let
+/// through: `messages` stands in for a required `prompt`, and a `string` field
+/// also takes an array (batches of texts, images as bytes). With `__raw` or
+/// `__category` the caller is choosing the backend's shape, so only the value
+/// constraints apply.
+pub fn validate_against_schema(schema: &serde_json::Value, args: &serde_json::Value) -> Result<(), Vec<SchemaViolation>> {
+    let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) else {
+        return Ok(());
+    };
+    let mut violations = Vec::new();
+    let mut violation = |field: &str, code, message: String| {
+        violations.push(SchemaViolation { field: field.to_string(), code, message });
+    };
+
+    let shape_checked = args.get("__raw").is_none() && args.get("__category").is_none();
+
+    let required = schema.get("required").and_then(|r| r.as_array()).into_iter().flatten();
+    for name in required.filter_map(|r| r.as_str()).filter(|_| shape_checked) {
+        let present = args.get(name).is_some_and(|v| !v.is_null())
+            || (name == "prompt" && args.get("messages").is_some());
+        if !present {
+            violation(name, "required", format!("'{}' is required", name));
+        }
+    }
+
+    for (name, property) in properties {
+        let Some(value) = args.get(name) else {
+            continue;
+        };
+
+        if let Some(expected) = property.get("type").and_then(|t| t.as_str()).filter(|_| shape_checked) {
+            let matches = match expected {
+                "string" => value.is_string() || value.is_array(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "number" => value.is_number(),
+                "boolean" => value.is_boolean(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                _ => true,
+            };
+            if !matches {
+                violation(name, "type", format!("'{}' must be of type {}", name, expected));
+                continue;
+            }
+        }
+
+        if let Some(allowed) = property.get("enum").and_then(|e| e.as_array()) {
+            if !allowed.contains(value) {
+                let allowed: Vec<String> = allowed.iter().map(|v| v.to_string()).collect();
+                violation(name, "enum", format!("'{}' must be one of: {}", name, allowed.join(", ")));
+            }
+        }
+
+        if let Some(number) = value.as_f64() {
+            if let Some(min) = property.get("minimum").and_then(|m| m.as_f64()).filter(|min| number < *min) {
+                violation(name, "minimum", format!("'{}' must be at least {}", name, min));
+            }
+            if let Some(max) = property.get("maximum").and_then(|m| m.as_f64()).filter(|max| number > *max) {
+                violation(name, "maximum", format!("'{}' must be at most {}", name, max));
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// -32602 listing every violation in `data.validation_errors`, with a summary message.
+pub fn validation_error(violations: Vec<SchemaViolation>) -> JsonRpcError {
+    let message = match violations.as_slice() {
+        [only] => only.message.clone(),
+        all => format!(
+            "{} invalid arguments: {}",
+            all.len(),
+            all.iter().map(|v| v.message.as_str()).collect::<Vec<_>>().join("; ")
+        ),
+    };
+    let mut error = JsonRpcError::invalid_params(message);
+    error.data = Some(json!({ "validation_errors": violations }));
+    error
+}
+
+/// Reject `prompt` or `text` arguments that are empty or only whitespace, which
+/// would otherwise cost a backend call that just errors. Arrays of texts are
+/// checked entry by entry.
+pub fn check_non_empty_inputs(args: &serde_json::Value) -> Result<(), String> {
+    for key in ["prompt", "text"] {
+        let blank = match args.get(key) {
+            Some(serde_json::Value::String(s)) => s.trim().is_empty(),
+            Some(serde_json::Value::Array(items)) => {
+                items.is_empty() || items.iter().any(|i| i.as_str().is_some_and(|s| s.trim().is_empty()))
+            }
+            _ => false,
+        };
+        if blank {
+            return Err(format!("'{}' must be non-empty", key));
+        }
+    }
+    Ok(())
+}
+
+/// Bound the structure of tool arguments: nesting deeper than `max_depth` or more
+/// than `max_keys` object keys in total is refused before anything walks them.
+/// Array elements don't count as keys, so byte arrays of media stay allowed.
+pub fn check_argument_complexity(args: &serde_json::Value, max_depth: usize, max_keys: usize) -> Result<(), String> {
+    let mut keys = 0usize;
+    let mut pending = vec![(args, 1usize)];
+    while let Some((value, depth)) = pending.pop() {
+        let children: Box<dyn Iterator<Item = &serde_json::Value>> = match value {
+            serde_json::Value::Object(map) => {
+                keys += map.len();
+                Box::new(map.values())
+            }
+            serde_json::Value::Array(items) => Box::new(items.iter()),
+            _ => continue,
+        };
+        if depth > max_depth {
+            return Err(format!("Arguments are nested more than {} levels deep", max_depth));
+        }
+        if keys > max_keys {
+            return Err(format!("Arguments have more than {} keys", max_keys));
+        }
+        pending.extend(children.filter(|c| c.is_object() || c.is_array()).map(|c| (c, depth + 1)));
+    }
+    Ok(())
+}
+
+/// Higher-level tools implemented in `crate::tasks` rather than a single model call.
+fn builtin_tools() -> Vec<Tool> {
+    vec![
+        Tool {
+            name: "__summarize".to_string(),
+            description: "Summarize text with an LLM, splitting long inputs and combining the partial summaries".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "text": { "type": "string", "description": "The text to summarize" },
+                    "max_length": { "type": "integer", "description": "Approximate maximum summary length in words", "default": 150 },
+                    "style": { "type": "string", "enum": ["paragraph", "bullets", "tldr"], "default": "paragraph" },
+                    "model": { "type": "string", "description": "LLM to use; defaults to the server's configured LLM" }
+                },
+                "required": ["text"]
+            }),
+        },
+        Tool {
+            name: "__translate".to_string(),
+            description: "Translate text between languages, chunking long inputs and preserving their order".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "text": { "type": "string", "description": "The text to translate" },
+                    "target_lang": { "type": "string", "description": "ISO 639-1 code of the target language (e.g. 'fr')" },
+                    "source_lang": { "type": "string", "description": "ISO 639-1 code of the source language; auto-detected if omitted" },
+                    "model": { "type": "string", "description": "LLM or translation model to use; defaults to the server's configured LLM" }
+                },
+                "required": ["text", "target_lang"]
+            }),
+        },
+        Tool {
+            name: "__prompt".to_string(),
+            description: "Run one of the server's configured prompts (see prompts/list) on the model it's bound to".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "Prompt name from prompts/list" },
+                    "arguments": { "type": "object", "description": "Values for the prompt's placeholders" }
+                },
+                "required": ["name"]
+            }),
+        },
+        Tool {
+            name: "__estimate".to_string(),
+            description: "Estimate a call's neurons and its split into prompt and completion tokens. Free; runs no model".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "model": { "type": "string", "description": "Model id, or a category name for its default model" },
+                    "arguments": { "type": "object", "description": "The arguments the call would be made with" }
+                },
+                "required": ["model"]
+            }),
+        },
+        Tool {
+            name: "__cost".to_string(),
+            description: "Explain the neuron estimate for a call: token counts, the formula's inputs and the NEURON_MULTIPLIER markup. Free; runs no model".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "model": { "type": "string", "description": "Model id, or a category name for its default model" },
+                    "arguments": { "type": "object", "description": "The arguments the call would be made with" }
+                },
+                "required": ["model"]
+            }),
+        },
+        Tool {
+            name: "__set_defaults".to_string(),
+            description: "Set default arguments (e.g. temperature, max_tokens) for this session's LLM calls; explicit arguments still win. Replaces earlier defaults; {} clears them".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "defaults": { "type": "object", "description": "Arguments merged under every later LLM call in this session" }
+                },
+                "required": ["defaults"]
+            }),
+        },
+        Tool {
+            name: "__capabilities".to_string(),
+            description: "List which features this server has enabled (streaming, vision, stats, ...). Free; runs no model".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+    ]
+}
+
+/// `compact` renders JSON results on one line instead of pretty-printing them.
+pub fn create_tool_result(result: serde_json::Value, is_error: bool, compact: bool) -> ToolResult {
     let text = if is_error {
         result.as_str().unwrap_or("Unknown error").to_string()
+    } else if compact {
+        compact_text(&result)
     } else {
-        serde_json::to_string_pretty(&result).unwrap_or_else(|_| result.to_string())
+        result_text(&result)
     };
 
     ToolResult {
         content: vec![ContentBlock::Text { text }],
+        structured_content: None,
         is_error: if is_error { Some(true) } else { None },
+        meta: None,
+    }
+}
+
+/// One text block per candidate completion, in order.
+pub fn create_candidates_result(candidates: serde_json::Value, compact: bool) -> ToolResult {
+    let candidates = match candidates {
+        serde_json::Value::Array(items) => items,
+        other => vec![other],
+    };
+    let render = if compact { compact_text } else { result_text };
+
+    ToolResult {
+        content: candidates.iter().map(|c| ContentBlock::Text { text: render(c) }).collect(),
+        structured_content: None,
+        is_error: None,
+        meta: None,
+    }
+}
+
+/// Render an AI result as text: bare strings and the `{ "response": "..." }` shape
+/// LLMs return become plain text, anything else is pretty-printed JSON.
+pub fn result_text(result: &serde_json::Value) -> String {
+    if let Some(text) = result.as_str() {
+        return text.to_string();
+    }
+
+    if let Some(text) = result.get("response").and_then(|r| r.as_str()) {
+        return text.to_string();
+    }
+
+    serde_json::to_string_pretty(result).unwrap_or_else(|_| result.to_string())
+}
+
+/// Like `result_text`, but JSON is rendered compactly.
+pub fn compact_text(result: &serde_json::Value) -> String {
+    match result.as_str().or_else(|| result.get("response").and_then(|r| r.as_str())) {
+        Some(text) => text.to_string(),
+        None => result.to_string(),
+    }
+}
+
+/// The result as a `structuredContent` object: JSON objects as-is, arrays
+/// wrapped as `{ items }`. Text results (strings and `{ response }`) have none.
+pub fn structured_content(result: &serde_json::Value) -> Option<serde_json::Value> {
+    match result {
+        serde_json::Value::Object(obj) if !obj.get("response").is_some_and(|r| r.is_string()) => {
+            Some(result.clone())
+        }
+        serde_json::Value::Array(_) => Some(json!({ "items": result })),
+        _ => None,
+    }
+}
+
+/// Cut a text result (a bare string or `{ "response": "..." }`) to `max_chars`
+/// characters. Returns the original length in characters if it was cut.
+pub fn truncate_text(result: &mut serde_json::Value, max_chars: usize) -> Option<usize> {
+    let text = if result.is_string() {
+        result
+    } else {
+        result.get_mut("response").filter(|r| r.is_string())?
+    };
+
+    let original = text.as_str()?;
+    let original_chars = original.chars().count();
+    if original_chars <= max_chars {
+        return None;
+    }
+
+    let cut: String = original.chars().take(max_chars).collect();
+    *text = serde_json::Value::String(cut);
+    Some(original_chars)
+}
+
+/// Reshape an embedding model's `{ shape, data: [[...], ...] }` into
+/// `{ embeddings: [{ index, vector }] }`, one entry per input in input order.
+/// Results without a `data` array are returned unchanged.
+pub fn embeddings_result(result: serde_json::Value) -> serde_json::Value {
+    let Some(data) = result.get("data").and_then(|d| d.as_array()) else {
+        return result;
+    };
+
+    // A single vector (flat array of numbers) counts as one input
+    let vectors: Vec<serde_json::Value> = if data.first().is_some_and(|v| v.is_number()) {
+        vec![serde_json::Value::Array(data.clone())]
+    } else {
+        data.clone()
+    };
+
+    let embeddings: Vec<serde_json::Value> = vectors
+        .into_iter()
+        .enumerate()
+        .map(|(index, vector)| json!({ "index": index, "vector": vector }))
+        .collect();
+
+    json!({ "embeddings": embeddings })
+}
+
+/// Like `embeddings_result`, but each vector is packed as little-endian float32
+/// and base64-encoded (OpenAI's `encoding_format: "base64"`). Also returns the
+/// vector dimension so callers can report it.
+pub fn base64_embeddings_result(result: serde_json::Value) -> (serde_json::Value, usize) {
+    let mut result = embeddings_result(result);
+    let mut dimensions = 0;
+
+    let entries = result.get_mut("embeddings").and_then(|e| e.as_array_mut());
+    for entry in entries.into_iter().flatten() {
+        let Some(vector) = entry.get("vector").and_then(|v| v.as_array()) else {
+            continue;
+        };
+        dimensions = vector.len();
+        let bytes: Vec<u8> = vector
+            .iter()
+            .flat_map(|x| (x.as_f64().unwrap_or(0.0) as f32).to_le_bytes())
+            .collect();
+        entry["vector"] = json!(media::encode_base64(&bytes));
+    }
+
+    (result, dimensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn result_text_takes_plain_strings_as_is() {
+        assert_eq!(result_text(&json!("already text")), "already text");
+        assert_eq!(compact_text(&json!("already text")), "already text");
+    }
+
+    #[test]
+    fn result_text_unwraps_response() {
+        assert_eq!(result_text(&json!({ "response": "hi", "usage": {} })), "hi");
+        assert_eq!(compact_text(&json!({ "response": "hi" })), "hi");
+    }
+
+    #[test]
+    fn result_text_renders_other_results_as_json() {
+        let result = json!({ "label": "cat", "score": 0.9 });
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&result_text(&result)).unwrap(), result);
+        assert_eq!(compact_text(&result), result.to_string());
+        // A non-string `response` isn't text
+        assert_eq!(compact_text(&json!({ "response": 3 })), r#"{"response":3}"#);
+    }
+
+    #[test]
+    fn truncate_text_cuts_by_characters() {
+        let mut result = json!("héllo wörld");
+        assert_eq!(truncate_text(&mut result, 5), Some(11));
+        assert_eq!(result, json!("héllo"));
+
+        let mut result = json!({ "response": "abcdef", "usage": { "prompt_tokens": 1 } });
+        assert_eq!(truncate_text(&mut result, 3), Some(6));
+        assert_eq!(result, json!({ "response": "abc", "usage": { "prompt_tokens": 1 } }));
+    }
+
+    #[test]
+    fn truncate_text_leaves_short_and_non_text_results() {
+        let mut result = json!("short");
+        assert_eq!(truncate_text(&mut result, 5), None);
+        assert_eq!(result, json!("short"));
+
+        let mut result = json!({ "data": [[0.1, 0.2]] });
+        assert_eq!(truncate_text(&mut result, 1), None);
+        assert_eq!(result, json!({ "data": [[0.1, 0.2]] }));
+    }
+
+    #[test]
+    fn blank_prompts_and_texts_are_rejected() {
+        assert!(check_non_empty_inputs(&json!({ "prompt": "hi" })).is_ok());
+        assert!(check_non_empty_inputs(&json!({ "messages": [] })).is_ok());
+        assert_eq!(check_non_empty_inputs(&json!({ "prompt": " \n\t" })), Err("'prompt' must be non-empty".to_string()));
+        assert_eq!(check_non_empty_inputs(&json!({ "text": [] })), Err("'text' must be non-empty".to_string()));
+        assert_eq!(check_non_empty_inputs(&json!({ "text": ["a", " "] })), Err("'text' must be non-empty".to_string()));
+    }
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "properties": {
+                "prompt": { "type": "string" },
+                "max_tokens": { "type": "integer", "minimum": 1, "maximum": 100 },
+                "pooling": { "type": "string", "enum": ["mean", "cls"] },
+            },
+            "required": ["prompt"],
+        })
+    }
+
+    fn codes(args: serde_json::Value) -> Vec<(String, &'static str)> {
+        match validate_against_schema(&schema(), &args) {
+            Ok(()) => Vec::new(),
+            Err(violations) => violations.into_iter().map(|v| (v.field, v.code)).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_arguments_pass_the_schema() {
+        assert!(codes(json!({ "prompt": "hi", "max_tokens": 100, "pooling": "cls" })).is_empty());
+        // `messages` stands in for `prompt`, and strings also take arrays
+        assert!(codes(json!({ "messages": [{ "role": "user", "content": "hi" }] })).is_empty());
+        assert!(codes(json!({ "prompt": ["a", "b"] })).is_empty());
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let found = codes(json!({ "max_tokens": 0, "pooling": "max" }));
+        assert_eq!(found, vec![
+            ("prompt".to_string(), "required"),
+            ("max_tokens".to_string(), "minimum"),
+            ("pooling".to_string(), "enum"),
+        ]);
+        assert_eq!(codes(json!({ "prompt": "hi", "max_tokens": 101 })), vec![("max_tokens".to_string(), "maximum")]);
+        assert_eq!(codes(json!({ "prompt": "hi", "max_tokens": "ten" })), vec![("max_tokens".to_string(), "type")]);
+    }
+
+    #[test]
+    fn raw_calls_only_get_value_checks() {
+        assert!(codes(json!({ "__raw": true, "max_tokens": "ten" })).is_empty());
+        assert_eq!(codes(json!({ "__raw": true, "max_tokens": 500 })), vec![("max_tokens".to_string(), "maximum")]);
+    }
+
+    #[test]
+    fn validation_error_lists_violations_in_data() {
+        let Err(violations) = validate_against_schema(&schema(), &json!({ "max_tokens": 0 })) else {
+            panic!("expected violations");
+        };
+        let error = validation_error(violations);
+        assert_eq!(error.code, -32602);
+        assert_eq!(error.message, "2 invalid arguments: 'prompt' is required; 'max_tokens' must be at least 1");
+        assert_eq!(error.data.unwrap()["validation_errors"].as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn argument_depth_is_bounded() {
+        let mut nested = json!("leaf");
+        for _ in 0..5 {
+            nested = json!({ "a": nested });
+        }
+        assert!(check_argument_complexity(&nested, 5, 100).is_ok());
+        assert_eq!(
+            check_argument_complexity(&nested, 4, 100),
+            Err("Arguments are nested more than 4 levels deep".to_string())
+        );
+        assert!(check_argument_complexity(&json!([[[1]]]), 2, 100).is_err());
+    }
+
+    #[test]
+    fn only_object_keys_count() {
+        let keys: serde_json::Map<String, serde_json::Value> = (0..11).map(|i| (i.to_string(), json!(i))).collect();
+        assert!(check_argument_complexity(&serde_json::Value::Object(keys.clone()), 4, 11).is_ok());
+        assert_eq!(
+            check_argument_complexity(&json!({ "nested": keys }), 4, 11),
+            Err("Arguments have more than 11 keys".to_string())
+        );
+        // Media bytes are long arrays, not keys
+        assert!(check_argument_complexity(&json!({ "image": vec![0u8; 10_000] }), 4, 1).is_ok());
     }
 }
@@ -0,0 +1,372 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use worker::*;
+use crate::ai::{AiBridge, AiError, ModelRegistry, TokenUsage};
+use crate::ai::media::{read_chunk, stream_reader};
+use crate::ai::models::ModelInfo;
+use crate::config;
+use crate::mcp::tools::result_text;
+use crate::openai::{resume, structured};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<Value>,
+    #[serde(default)]
+    pub stream: bool,
+    pub stream_options: Option<StreamOptions>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// `{ "type": "json_object" }` or `json_schema`; passed on to the model.
+    pub response_format: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+impl ChatCompletionRequest {
+    fn to_input(&self) -> Value {
+        let mut input = json!({ "messages": self.messages });
+        if let Some(max_tokens) = self.max_tokens {
+            input["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(temperature) = self.temperature {
+            input["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            input["top_p"] = json!(top_p);
+        }
+        if let Some(response_format) = &self.response_format {
+            input["response_format"] = response_format.clone();
+        }
+        input
+    }
+}
+
+/// `POST /v1/chat/completions`: OpenAI-compatible chat on top of `AiBridge`.
+pub async fn handle_chat_completions(mut req: Request, env: Env) -> Result<Response> {
+    let body: ChatCompletionRequest = match req.json().await {
+        Ok(body) => body,
+        Err(e) => return openai_error(400, "invalid_request_error", &format!("Invalid request body: {}", e)),
+    };
+
+    let id = completion_id();
+    let created = Date::now().as_millis() / 1000;
+
+    // Models that can't stream (embeddings, images, ...) still get an SSE
+    // response, just with the whole answer in one event
+    let streamable = ModelRegistry::lookup(&env, &body.model).is_some_and(|m| m.supports_streaming());
+    let include_usage = body.stream_options.as_ref().is_some_and(|o| o.include_usage);
+
+    if body.stream && streamable {
+        // With somewhere to buffer it, the generation outlives this connection
+        if resume::enabled(&env) {
+            return resume::start(&env, id, created, body).await;
+        }
+        let state = match chunk_stream(&env, &body, id, created).await {
+            Ok(state) => state,
+            Err(e) => return ai_error(e),
+        };
+
+        let headers = crate::cors_headers();
+        headers.set("Content-Type", "text/event-stream")?;
+        headers.set("Cache-Control", "no-cache")?;
+        return Response::from_stream(stream::unfold(state, ChunkStream::next))
+            .map(|r| r.with_headers(headers));
+    }
+
+    let result = match AiBridge::run_inference(&env, &body.model, body.to_input(), None).await {
+        Ok(result) => result,
+        Err(e) => return ai_error(e),
+    };
+
+    let content = result_text(&result.result);
+    let usage = usage_json(&result.usage.unwrap_or_default(), config::billed_neurons(&env, result.neurons_used));
+
+    if body.stream {
+        let mut events = chunk_event(&id, created, &result.model_used, json!([{
+            "index": 0,
+            "delta": { "role": "assistant", "content": content },
+            "finish_reason": null,
+        }]), None);
+        events.push_str(&chunk_event(&id, created, &result.model_used,
+            json!([{ "index": 0, "delta": {}, "finish_reason": "stop" }]), None));
+        if include_usage {
+            events.push_str(&chunk_event(&id, created, &result.model_used, json!([]), Some(usage)));
+        }
+        events.push_str("data: [DONE]\n\n");
+
+        let headers = crate::cors_headers();
+        headers.set("Content-Type", "text/event-stream")?;
+        headers.set("Cache-Control", "no-cache")?;
+        return Response::ok(events).map(|r| r.with_headers(headers));
+    }
+
+    crate::json_response(&json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": result.model_used,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": "stop",
+        }],
+        "usage": usage,
+    }))
+}
+
+/// Start the model's stream for `body`, re-framed as chat completion chunks.
+pub(crate) async fn chunk_stream(
+    env: &Env,
+    body: &ChatCompletionRequest,
+    id: String,
+    created: u64,
+) -> std::result::Result<ChunkStream, AiError> {
+    let ai_stream = AiBridge::run_stream(env, &body.model, body.to_input()).await?;
+    let reader = stream_reader(&ai_stream.body).map_err(|e| AiError::Backend(e.to_string()))?;
+
+    Ok(ChunkStream {
+        reader,
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+        id,
+        created,
+        model: ai_stream.model_used,
+        include_usage: body.stream_options.as_ref().is_some_and(|o| o.include_usage),
+        prompt_tokens: ModelInfo::estimate_prompt_tokens(&body.to_input()),
+        completion_chars: 0,
+        estimated_neurons: config::billed_neurons(env, ai_stream.estimated_neurons),
+        usage: None,
+        sent_role: false,
+        structured: structured::wants_json(body.response_format.as_ref()),
+        text: String::new(),
+    })
+}
+
+fn usage_json(usage: &TokenUsage, neurons: u32) -> Value {
+    json!({
+        "prompt_tokens": usage.prompt_tokens,
+        "completion_tokens": usage.completion_tokens,
+        "total_tokens": usage.total_tokens(),
+        "neurons": neurons,
+    })
+}
+
+/// Re-frames Cloudflare's `{ "response": "..." }` SSE events as OpenAI
+/// `chat.completion.chunk` events, ending with `data: [DONE]`.
+///
+/// When JSON output was requested the text is held back instead: chunks carry
+/// an empty delta plus `partial`, a repaired view of the JSON so far, and the
+/// final chunk the validated JSON (or, if it doesn't parse, the raw text with
+/// `isError: true`).
+pub(crate) struct ChunkStream {
+    reader: JsValue,
+    buffer: Vec<u8>,
+    pending: VecDeque<Vec<u8>>,
+    done: bool,
+    id: String,
+    created: u64,
+    model: String,
+    include_usage: bool,
+    prompt_tokens: u32,
+    completion_chars: usize,
+    estimated_neurons: u32,
+    usage: Option<Value>,
+    sent_role: bool,
+    structured: bool,
+    /// All text so far, in structured mode.
+    text: String,
+}
+
+impl ChunkStream {
+    pub(crate) async fn next(mut self) -> Option<(Result<Vec<u8>>, Self)> {
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return Some((Ok(chunk), self));
+            }
+            if self.done {
+                return None;
+            }
+
+            match read_chunk(&self.reader).await {
+                Ok(Some(bytes)) => {
+                    self.buffer.extend_from_slice(&bytes);
+                    self.drain_events();
+                }
+                Ok(None) => self.finish(),
+                Err(e) => {
+                    self.done = true;
+                    return Some((Err(e), self));
+                }
+            }
+        }
+    }
+
+    fn drain_events(&mut self) {
+        while let Some(data) = next_event(&mut self.buffer) {
+            if data == "[DONE]" {
+                self.finish();
+                return;
+            }
+
+            let Ok(event) = serde_json::from_str::<Value>(&data) else {
+                continue;
+            };
+
+            if let Some(usage) = event.get("usage") {
+                self.usage = Some(usage.clone());
+            }
+
+            if let Some(text) = event.get("response").and_then(|r| r.as_str()) {
+                if text.is_empty() {
+                    continue;
+                }
+                self.completion_chars += text.len();
+
+                if self.structured {
+                    self.text.push_str(text);
+                    let delta = self.delta(None);
+                    let mut chunk = chunk_json(&self.id, self.created, &self.model,
+                        json!([{ "index": 0, "delta": delta, "finish_reason": null }]), None);
+                    if let Some(partial) = structured::repair_partial(&self.text) {
+                        chunk["partial"] = partial;
+                    }
+                    self.push_event(chunk);
+                    continue;
+                }
+
+                let delta = self.delta(Some(text));
+                self.push_chunk(json!([{ "index": 0, "delta": delta, "finish_reason": null }]), None);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+
+        if self.structured {
+            // The only chunk with content: the whole JSON once it validates
+            let parsed = structured::parse_complete(&self.text);
+            let content = parsed.as_ref().map_or_else(|| self.text.clone(), Value::to_string);
+            let delta = self.delta(Some(&content));
+            let mut chunk = chunk_json(&self.id, self.created, &self.model,
+                json!([{ "index": 0, "delta": delta, "finish_reason": "stop" }]), None);
+            if parsed.is_none() {
+                chunk["isError"] = json!(true);
+            }
+            self.push_event(chunk);
+        } else {
+            self.push_chunk(json!([{ "index": 0, "delta": {}, "finish_reason": "stop" }]), None);
+        }
+
+        // Only sent when the caller asked via stream_options.include_usage
+        if self.include_usage {
+            // Prefer the backend's token counts; estimate from text lengths otherwise
+            let usage = self.usage.as_ref()
+                .and_then(TokenUsage::from_reported)
+                .unwrap_or(TokenUsage {
+                    prompt_tokens: self.prompt_tokens,
+                    completion_tokens: (self.completion_chars / 4) as u32,
+                });
+            self.push_chunk(json!([]), Some(usage_json(&usage, self.estimated_neurons)));
+        }
+
+        self.pending.push_back(b"data: [DONE]\n\n".to_vec());
+    }
+
+    /// A delta with `content`, carrying the role on the first one sent.
+    fn delta(&mut self, content: Option<&str>) -> Value {
+        let mut delta = json!({});
+        if let Some(content) = content {
+            delta["content"] = json!(content);
+        }
+        if !self.sent_role {
+            delta["role"] = json!("assistant");
+            self.sent_role = true;
+        }
+        delta
+    }
+
+    fn push_chunk(&mut self, choices: Value, usage: Option<Value>) {
+        let event = chunk_event(&self.id, self.created, &self.model, choices, usage);
+        self.pending.push_back(event.into_bytes());
+    }
+
+    fn push_event(&mut self, chunk: Value) {
+        self.pending.push_back(format!("data: {}\n\n", chunk).into_bytes());
+    }
+}
+
+/// Format one `chat.completion.chunk` as an SSE `data:` event.
+fn chunk_event(id: &str, created: u64, model: &str, choices: Value, usage: Option<Value>) -> String {
+    format!("data: {}\n\n", chunk_json(id, created, model, choices, usage))
+}
+
+fn chunk_json(id: &str, created: u64, model: &str, choices: Value, usage: Option<Value>) -> Value {
+    let mut chunk = json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": choices,
+    });
+    if let Some(usage) = usage {
+        chunk["usage"] = usage;
+    }
+    chunk
+}
+
+/// Pop one complete SSE event off the front of `buffer`, returning its joined
+/// `data:` lines. Returns `None` until a blank-line terminator has arrived.
+fn next_event(buffer: &mut Vec<u8>) -> Option<String> {
+    let end = buffer.windows(2).position(|w| w == b"\n\n")?;
+    let event: Vec<u8> = buffer.drain(..end + 2).collect();
+    let event = String::from_utf8_lossy(&event[..end]);
+
+    let data: Vec<&str> = event
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+
+    Some(data.join("\n"))
+}
+
+fn completion_id() -> String {
+    let mut bytes = [0u8; 12];
+    let _ = getrandom::getrandom(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("chatcmpl-{}", hex)
+}
+
+pub(crate) fn ai_error(e: AiError) -> Result<Response> {
+    match e {
+        AiError::InvalidInput(msg) | AiError::InvalidUrl(msg) => openai_error(400, "invalid_request_error", &msg),
+        AiError::UnknownModel(id) => openai_error(404, "invalid_request_error", &format!("The model '{}' does not exist", id)),
+        AiError::Backend(msg) => openai_error(500, "api_error", &format!("AI inference failed: {}", msg)),
+        AiError::Unavailable(msg) => openai_error(503, "api_error", &msg),
+    }
+}
+
+pub(crate) fn openai_error(status: u16, kind: &str, message: &str) -> Result<Response> {
+    let body = json!({
+        "error": { "message": message, "type": kind, "code": null }
+    });
+    crate::json_response(&body).map(|r| r.with_status(status))
+}
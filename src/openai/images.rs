@@ -0,0 +1,173 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use worker::*;
+use crate::ai::{AiBridge, ModelRegistry};
+use crate::ai::models::ModelCategory;
+use crate::config;
+use crate::openai::chat::{ai_error, openai_error};
+use serde::Deserialize;
+use serde_json::json;
+
+const DEFAULT_PROMPT: &str = "Describe this image.";
+/// Each image is a separate (sequential) inference call.
+const MAX_IMAGES: u32 = 4;
+
+#[derive(Debug, Deserialize)]
+pub struct ImageGenerationRequest {
+    pub model: Option<String>,
+    pub prompt: String,
+    pub n: Option<u32>,
+    pub size: Option<String>,
+    pub response_format: Option<String>,
+}
+
+/// `POST /v1/images/describe`: `multipart/form-data` with an `image` file and
+/// optional `prompt` and `model` fields, so clients can skip base64 entirely.
+pub async fn handle_image_describe(mut req: Request, env: Env) -> Result<Response> {
+    let max_body_bytes = config::max_body_bytes(&env);
+
+    let is_multipart = req
+        .headers()
+        .get("Content-Type")?
+        .is_some_and(|t| t.starts_with("multipart/form-data"));
+    if !is_multipart {
+        return openai_error(415, "invalid_request_error", "Expected a multipart/form-data body");
+    }
+
+    // Reject oversized uploads up front when the client declares a length
+    let declared = req
+        .headers()
+        .get("Content-Length")?
+        .and_then(|l| l.parse::<usize>().ok());
+    if declared.is_some_and(|l| l > max_body_bytes) {
+        return too_large(max_body_bytes);
+    }
+
+    let form = match req.form_data().await {
+        Ok(form) => form,
+        Err(e) => return openai_error(400, "invalid_request_error", &format!("Invalid form data: {}", e)),
+    };
+
+    let image = match form.get("image") {
+        Some(FormEntry::File(file)) => file.bytes().await?,
+        _ => return openai_error(400, "invalid_request_error", "Missing 'image' file part"),
+    };
+    if image.len() > max_body_bytes {
+        return too_large(max_body_bytes);
+    }
+
+    let field = |name: &str| match form.get(name) {
+        Some(FormEntry::Field(value)) if !value.trim().is_empty() => Some(value),
+        _ => None,
+    };
+    let prompt = field("prompt").unwrap_or_else(|| DEFAULT_PROMPT.to_string());
+    let model = field("model").unwrap_or_else(|| ModelRegistry::default_for(&env, &ModelCategory::ImageToText));
+
+    // The category hint makes unlisted vision models get their image decoded too
+    let input = json!({
+        "image": image,
+        "prompt": prompt,
+        "__category": ModelCategory::ImageToText,
+    });
+
+    let result = match AiBridge::run_inference(&env, &model, input, None).await {
+        Ok(result) => result,
+        Err(e) => return ai_error(e),
+    };
+
+    // Vision-language models answer in `description`/`response`; classifiers return labels
+    let description = result.result.get("description")
+        .or_else(|| result.result.get("response"))
+        .and_then(|d| d.as_str())
+        .map(|d| d.trim().to_string());
+
+    let mut body = json!({
+        "model": result.model_used,
+        "neurons_used": config::billed_neurons(&env, result.neurons_used),
+    });
+    match description {
+        Some(description) => body["description"] = json!(description),
+        None => body["result"] = result.result,
+    }
+
+    crate::json_response(&body)
+}
+
+/// `POST /v1/images/generations`: OpenAI-compatible image generation returning `b64_json`.
+pub async fn handle_image_generations(mut req: Request, env: Env) -> Result<Response> {
+    let body: ImageGenerationRequest = match req.json().await {
+        Ok(body) => body,
+        Err(e) => return openai_error(400, "invalid_request_error", &format!("Invalid request body: {}", e)),
+    };
+
+    if body.response_format.as_deref().is_some_and(|f| f != "b64_json") {
+        return openai_error(
+            400,
+            "invalid_request_error",
+            "Only response_format 'b64_json' is supported; image hosting is not configured",
+        );
+    }
+
+    let n = body.n.unwrap_or(1);
+    if !(1..=MAX_IMAGES).contains(&n) {
+        return openai_error(400, "invalid_request_error", &format!("'n' must be between 1 and {}", MAX_IMAGES));
+    }
+
+    let mut input = json!({ "prompt": body.prompt });
+    if let Some(size) = body.size.as_deref() {
+        match parse_size(size) {
+            Some((width, height)) => {
+                input["width"] = json!(width);
+                input["height"] = json!(height);
+            }
+            None => {
+                return openai_error(
+                    400,
+                    "invalid_request_error",
+                    "'size' must look like 1024x1024, with each side between 256 and 2048",
+                )
+            }
+        }
+    }
+
+    let model = body.model.unwrap_or_else(|| ModelRegistry::default_for(&env, &ModelCategory::Image));
+
+    let mut data = Vec::with_capacity(n as usize);
+    let mut neurons_used = 0;
+    for _ in 0..n {
+        let result = match AiBridge::run_inference(&env, &model, input.clone(), None).await {
+            Ok(result) => result,
+            Err(e) => return ai_error(e),
+        };
+        neurons_used += result.neurons_used;
+
+        let Some(image) = result.result.get("image").and_then(|i| i.as_str()) else {
+            return openai_error(500, "api_error", &format!("{} did not return an image", result.model_used));
+        };
+        data.push(json!({ "b64_json": image }));
+    }
+
+    crate::json_response(&json!({
+        "created": Date::now().as_millis() / 1000,
+        "data": data,
+        "neurons_used": config::billed_neurons(&env, neurons_used),
+    }))
+}
+
+/// Parse an OpenAI `size` such as `"1024x1024"` into `(width, height)`.
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    let width: u32 = width.trim().parse().ok()?;
+    let height: u32 = height.trim().parse().ok()?;
+    let valid = |side: u32| (256..=2048).contains(&side);
+    (valid(width) && valid(height)).then_some((width, height))
+}
+
+fn too_large(limit: usize) -> Result<Response> {
+    openai_error(
+        413,
+        "invalid_request_error",
+        &format!("Image exceeds the {} byte limit (MAX_BODY_BYTES)", limit),
+    )
+}
@@ -0,0 +1,10 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+pub mod chat;
+pub mod images;
+pub mod resume;
+pub mod structured;
+
+pub use chat::handle_chat_completions;
+pub use images::{handle_image_describe, handle_image_generations};
@@ -0,0 +1,221 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Resumable chat completion streams. With a Durable Object bound as
+//! `GENERATIONS`, a streamed completion runs inside the object named by its
+//! completion id rather than in the client's request: each chunk is buffered
+//! under an SSE `id:`, and `GET /v1/chat/completions/<id>` with `Last-Event-ID`
+//! picks up after the last chunk the client saw.
+//!
+//! Generations are found by completion id rather than an MCP session id, since
+//! `/v1/chat/completions` has no sessions. The id is 96 random bits and only
+//! ever sent to the client that started the generation, so knowing it is what
+//! lets a client resume.
+//!
+//! Buffers only live in the object's memory. While a generation runs, an alarm
+//! every few seconds gives the object work to do, which makes it likely to stay
+//! in memory after the client drops, but the runtime can still evict it; then
+//! the generation is lost. A finished buffer is kept for `LINGER_MS`, long
+//! enough for a client to reconnect, and then cleared.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use worker::*;
+use crate::openai::chat::{self, ChatCompletionRequest, ChunkStream};
+
+const BINDING: &str = "GENERATIONS";
+
+/// How often a response that has caught up checks for new chunks.
+const POLL_MS: u64 = 50;
+
+/// How often the alarm fires while a generation is running.
+const KEEPALIVE_MS: u64 = 5_000;
+
+/// How long a finished buffer can still be resumed before it's cleared.
+const LINGER_MS: u64 = 60_000;
+
+/// What the worker hands the object to start a generation.
+#[derive(Serialize, Deserialize)]
+struct Start {
+    id: String,
+    created: u64,
+    request: ChatCompletionRequest,
+}
+
+/// The SSE events produced so far, each already carrying its `id:` line.
+#[derive(Default)]
+struct Buffer {
+    events: Vec<Vec<u8>>,
+    /// When the generation ended, as a Unix time in milliseconds.
+    finished_ms: Option<u64>,
+}
+
+type Shared = Rc<RefCell<Option<Buffer>>>;
+
+#[durable_object]
+pub struct GenerationBuffer {
+    state: State,
+    /// `None` until a generation starts, and again once it has been cleared.
+    buffer: Shared,
+    env: Env,
+}
+
+impl DurableObject for GenerationBuffer {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            state,
+            buffer: Rc::new(RefCell::new(None)),
+            env,
+        }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        match (req.method(), req.path().as_str()) {
+            (Method::Post, "/start") => {
+                if self.buffer.borrow().is_some() {
+                    return chat::openai_error(409, "invalid_request_error", "Generation already started");
+                }
+                let start: Start = req.json().await?;
+
+                // Claimed before the model call so a second start can't slip in
+                *self.buffer.borrow_mut() = Some(Buffer::default());
+                let chunks = match chat::chunk_stream(&self.env, &start.request, start.id, start.created).await {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        *self.buffer.borrow_mut() = None;
+                        return chat::ai_error(e);
+                    }
+                };
+
+                // Not tied to this request, so it keeps going if the client
+                // drops, for as long as the object stays in memory
+                wasm_bindgen_futures::spawn_local(pump(chunks, self.buffer.clone()));
+                self.state.storage().set_alarm(std::time::Duration::from_millis(KEEPALIVE_MS)).await?;
+                tail(self.buffer.clone(), 0)
+            }
+            (Method::Get, "/resume") => {
+                if self.buffer.borrow().is_none() {
+                    return chat::openai_error(
+                        404,
+                        "invalid_request_error",
+                        "No generation to resume with that id; it never started or is no longer buffered",
+                    );
+                }
+                let after = req.headers()
+                    .get("Last-Event-ID")?
+                    .and_then(|id| id.trim().parse::<usize>().ok());
+                tail(self.buffer.clone(), after.map_or(0, |id| id + 1))
+            }
+            _ => Response::error("Not found", 404),
+        }
+    }
+
+    /// Keep a running generation's object busy, and clear the buffer once it
+    /// has been finished for `LINGER_MS`.
+    async fn alarm(&self) -> Result<Response> {
+        let finished_ms = match self.buffer.borrow().as_ref() {
+            Some(buffer) => buffer.finished_ms,
+            // Evicted since the last alarm, or already cleared
+            None => return Response::empty(),
+        };
+        let next_ms = match finished_ms {
+            None => KEEPALIVE_MS,
+            Some(finished_ms) => {
+                let since = Date::now().as_millis().saturating_sub(finished_ms);
+                if since >= LINGER_MS {
+                    *self.buffer.borrow_mut() = None;
+                    return Response::empty();
+                }
+                LINGER_MS - since
+            }
+        };
+        self.state.storage().set_alarm(std::time::Duration::from_millis(next_ms)).await?;
+        Response::empty()
+    }
+}
+
+/// Read the model's stream to the end, whoever is listening.
+async fn pump(chunks: ChunkStream, buffer: Shared) {
+    let mut chunks = Box::pin(stream::unfold(chunks, ChunkStream::next));
+    while let Some(chunk) = chunks.next().await {
+        let event = match chunk {
+            Ok(event) => event,
+            Err(e) => {
+                console_error!("Buffered generation failed: {}", e);
+                break;
+            }
+        };
+        if let Some(buffer) = buffer.borrow_mut().as_mut() {
+            let mut numbered = format!("id: {}\n", buffer.events.len()).into_bytes();
+            numbered.extend_from_slice(&event);
+            buffer.events.push(numbered);
+        }
+    }
+    if let Some(buffer) = buffer.borrow_mut().as_mut() {
+        buffer.finished_ms = Some(Date::now().as_millis());
+    }
+}
+
+/// An SSE response replaying the buffer from event `from`, then following it
+/// until the generation is done.
+fn tail(buffer: Shared, from: usize) -> Result<Response> {
+    let events = stream::unfold((buffer, from), |(buffer, next)| async move {
+        loop {
+            let (event, done) = {
+                let guard = buffer.borrow();
+                let current = guard.as_ref()?;
+                (current.events.get(next).cloned(), current.finished_ms.is_some())
+            };
+            if let Some(event) = event {
+                return Some((Ok::<_, Error>(event), (buffer, next + 1)));
+            }
+            if done {
+                return None;
+            }
+            Delay::from(std::time::Duration::from_millis(POLL_MS)).await;
+        }
+    });
+
+    let headers = crate::cors_headers();
+    headers.set("Content-Type", "text/event-stream")?;
+    headers.set("Cache-Control", "no-cache")?;
+    Response::from_stream(events).map(|r| r.with_headers(headers))
+}
+
+/// Whether streamed completions go through the `GENERATIONS` object.
+pub fn enabled(env: &Env) -> bool {
+    env.durable_object(BINDING).is_ok()
+}
+
+/// Run `request` in the generation object for `id` and stream it from there.
+pub async fn start(env: &Env, id: String, created: u64, request: ChatCompletionRequest) -> Result<Response> {
+    let stub = env.durable_object(BINDING)?.id_from_name(&id)?.get_stub()?;
+    let body = serde_json::to_string(&Start { id, created, request })?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post).with_body(Some(body.into()));
+    stub.fetch_with_request(Request::new_with_init("https://generation/start", &init)?).await
+}
+
+/// `GET /v1/chat/completions/<id>`: the rest of a buffered generation, after the
+/// `Last-Event-ID` the client last saw (from the start without one).
+pub async fn handle_resume(req: &Request, env: &Env, id: &str) -> Result<Response> {
+    if !enabled(env) {
+        return chat::openai_error(404, "invalid_request_error", "Resuming streams needs the GENERATIONS Durable Object");
+    }
+    if !id.starts_with("chatcmpl-") {
+        return chat::openai_error(404, "invalid_request_error", &format!("No completion '{}'", id));
+    }
+
+    let headers = Headers::new();
+    if let Some(last_event_id) = req.headers().get("Last-Event-ID")? {
+        headers.set("Last-Event-ID", &last_event_id)?;
+    }
+    let mut init = RequestInit::new();
+    init.with_headers(headers);
+
+    let stub = env.durable_object(BINDING)?.id_from_name(id)?.get_stub()?;
+    stub.fetch_with_request(Request::new_with_init("https://generation/resume", &init)?).await
+}
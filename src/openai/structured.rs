@@ -0,0 +1,142 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! JSON output (`response_format`) for streamed chat completions. Partial JSON
+//! isn't valid until the model is done, so the stream carries best-effort
+//! repairs of what has arrived for progress display, and the final chunk the
+//! validated result.
+
+use serde_json::Value;
+
+/// How many cut points `repair_partial` tries before giving up on a repair.
+const MAX_REPAIR_CUTS: usize = 3;
+
+/// Whether a `response_format` asks for JSON (`json_object` or `json_schema`).
+pub fn wants_json(response_format: Option<&Value>) -> bool {
+    let kind = response_format.and_then(|f| f.get("type")).and_then(|t| t.as_str());
+    matches!(kind, Some("json_object" | "json_schema"))
+}
+
+/// The complete output as JSON, tolerating a Markdown code fence or chatter
+/// around it. `None` if there's no valid JSON value in it.
+pub fn parse_complete(text: &str) -> Option<Value> {
+    let start = text.find(['{', '['])?;
+    let end = text.rfind(['}', ']'])?;
+    if end < start {
+        return None;
+    }
+    serde_json::from_str(&text[start..=end]).ok()
+}
+
+/// A valid JSON value for output that's still arriving: open strings and
+/// containers are closed, and a trailing member too incomplete for that is
+/// dropped. `None` before anything JSON-like has arrived.
+pub fn repair_partial(text: &str) -> Option<Value> {
+    let start = text.find(['{', '['])?;
+    let text = &text[start..];
+
+    // Closers still owed, and the state after each comma, where an incomplete
+    // last member can be cut off
+    let mut closers: Vec<char> = Vec::new();
+    let mut cuts: Vec<(usize, Vec<char>)> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+                if closers.is_empty() {
+                    return serde_json::from_str(&text[..=i]).ok();
+                }
+            }
+            ',' => cuts.push((i, closers.clone())),
+            _ => {}
+        }
+    }
+
+    let closed = |prefix: &str, closers: &[char]| -> Option<Value> {
+        let mut candidate = prefix.to_string();
+        candidate.extend(closers.iter().rev());
+        serde_json::from_str(&candidate).ok()
+    };
+
+    let mut whole = text.to_string();
+    if in_string {
+        if escaped {
+            whole.pop();
+        }
+        whole.push('"');
+    }
+    if let Some(value) = closed(&whole, &closers) {
+        return Some(value);
+    }
+
+    for (at, closers) in cuts.iter().rev().take(MAX_REPAIR_CUTS) {
+        if let Some(value) = closed(&text[..*at], closers) {
+            return Some(value);
+        }
+    }
+
+    // Nothing complete yet: the outermost container, empty
+    closed(&text[..1], &closers[..1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn wants_json_for_json_formats_only() {
+        assert!(wants_json(Some(&json!({ "type": "json_object" }))));
+        assert!(wants_json(Some(&json!({ "type": "json_schema", "json_schema": {} }))));
+        assert!(!wants_json(Some(&json!({ "type": "text" }))));
+        assert!(!wants_json(None));
+    }
+
+    #[test]
+    fn parse_complete_tolerates_fences_and_chatter() {
+        assert_eq!(parse_complete(r#"{"a": 1}"#), Some(json!({ "a": 1 })));
+        assert_eq!(parse_complete("```json\n[1, 2]\n```"), Some(json!([1, 2])));
+        assert_eq!(parse_complete(r#"Sure! {"ok": true} Hope that helps."#), Some(json!({ "ok": true })));
+        assert_eq!(parse_complete(r#"{"a": "#), None);
+        assert_eq!(parse_complete("no json here"), None);
+    }
+
+    #[test]
+    fn repair_closes_open_strings_and_containers() {
+        assert_eq!(repair_partial(r#"{"name": "Ada", "tags": ["x", "y"#), Some(json!({ "name": "Ada", "tags": ["x", "y"] })));
+        assert_eq!(repair_partial(r#"{"quote": "say \"hi"#), Some(json!({ "quote": "say \"hi" })));
+    }
+
+    #[test]
+    fn repair_drops_an_incomplete_last_member() {
+        assert_eq!(repair_partial(r#"{"a": 1, "b": "#), Some(json!({ "a": 1 })));
+        assert_eq!(repair_partial(r#"{"a": 1, "b"#), Some(json!({ "a": 1 })));
+        assert_eq!(repair_partial(r#"{"a": tr"#), Some(json!({})));
+    }
+
+    #[test]
+    fn repair_stops_at_the_first_complete_value() {
+        assert_eq!(repair_partial(r#"```json {"a": [1]} trailing"#), Some(json!({ "a": [1] })));
+    }
+
+    #[test]
+    fn repair_needs_a_container_to_start() {
+        assert_eq!(repair_partial("Thinking..."), None);
+        assert_eq!(repair_partial(""), None);
+    }
+}
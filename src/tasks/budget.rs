@@ -0,0 +1,82 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::cell::Cell;
+use std::future::Future;
+use worker::{Date, Env};
+use crate::ai::AiError;
+use crate::config;
+use crate::mcp::session;
+
+/// Comfortably under the Workers wall-clock limits for a single request.
+const DEFAULT_BUDGET_MS: u64 = 25_000;
+
+/// Wall-clock budget for one `tools/call`, so multi-step tasks can stop early
+/// with a partial result instead of being killed mid-response.
+pub struct TimeBudget {
+    started_ms: u64,
+    limit_ms: u64,
+    slowest_call_ms: Cell<u64>,
+    /// The MCP session the call belongs to, checked before each sub-call.
+    session: Option<(Env, String)>,
+}
+
+impl TimeBudget {
+    /// Starts the clock now, with the limit from `REQUEST_BUDGET_MS`.
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            started_ms: Date::now().as_millis(),
+            limit_ms: Self::limit_ms(env),
+            slowest_call_ms: Cell::new(0),
+            session: None,
+        }
+    }
+
+    /// Stop starting sub-calls once `session_id` is closed, even by a
+    /// `DELETE /mcp` another isolate handled.
+    pub fn in_session(mut self, env: &Env, session_id: Option<&str>) -> Self {
+        self.session = session_id.map(|id| (env.clone(), id.to_string()));
+        self
+    }
+
+    /// End the budget at `deadline_ms` (a Unix time in milliseconds) if that
+    /// comes first, e.g. the caller's own timeout.
+    pub fn until(mut self, deadline_ms: u64) -> Self {
+        self.limit_ms = self.limit_ms.min(deadline_ms.saturating_sub(self.started_ms));
+        self
+    }
+
+    /// When the budget runs out, as a Unix time in milliseconds.
+    pub fn deadline_ms(&self) -> u64 {
+        self.started_ms + self.limit_ms
+    }
+
+    /// The configured budget (`REQUEST_BUDGET_MS`).
+    pub fn limit_ms(env: &Env) -> u64 {
+        config::number(env, "REQUEST_BUDGET_MS", DEFAULT_BUDGET_MS)
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        Date::now().as_millis().saturating_sub(self.started_ms)
+    }
+
+    /// Whether another sub-call is likely to finish in time, judging by the slowest one so far.
+    pub fn allows_another_call(&self) -> bool {
+        self.elapsed_ms() + self.slowest_call_ms.get() < self.limit_ms
+    }
+
+    /// Run one sub-call, recording how long it took. Refused without running if
+    /// the session has been closed since the call began.
+    pub async fn track<T, F: Future<Output = Result<T, AiError>>>(&self, call: F) -> Result<T, AiError> {
+        if let Some((env, session_id)) = &self.session {
+            if session::status(env, session_id).await.closed {
+                return Err(AiError::Unavailable("Request cancelled: session closed".to_string()));
+            }
+        }
+        let started = Date::now().as_millis();
+        let output = call.await;
+        let took = Date::now().as_millis().saturating_sub(started);
+        self.slowest_call_ms.set(self.slowest_call_ms.get().max(took));
+        output
+    }
+}
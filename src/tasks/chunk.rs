@@ -0,0 +1,84 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+/// Split `text` into pieces of at most `max_chars` bytes, preferring paragraph,
+/// then sentence, then word boundaries. Pieces are returned in order.
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+
+    while rest.len() > max_chars {
+        // Always make progress, even if a single character exceeds `max_chars`
+        let end = match floor_char_boundary(rest, max_chars) {
+            0 => rest.chars().next().map_or(1, char::len_utf8),
+            end => end,
+        };
+        let window = &rest[..end];
+        let cut = window.rfind("\n\n")
+            .or_else(|| window.rfind(". ").map(|i| i + 1))
+            .or_else(|| window.rfind(char::is_whitespace))
+            .filter(|&i| i > 0)
+            .unwrap_or(window.len());
+
+        chunks.push(rest[..cut].trim().to_string());
+        rest = rest[cut..].trim_start();
+    }
+
+    if !rest.is_empty() {
+        chunks.push(rest.to_string());
+    }
+
+    chunks
+}
+
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    let mut index = index.min(s.len());
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(chunk_text("  hello world  ", 100), vec!["hello world"]);
+        assert!(chunk_text("   ", 100).is_empty());
+    }
+
+    #[test]
+    fn prefers_paragraph_then_sentence_then_word_boundaries() {
+        assert_eq!(chunk_text("First para.\n\nSecond para.", 20), vec!["First para.", "Second para."]);
+        assert_eq!(chunk_text("One two. Three four five.", 15), vec!["One two.", "Three four", "five."]);
+        assert_eq!(chunk_text("alpha beta gamma", 11), vec!["alpha beta", "gamma"]);
+    }
+
+    #[test]
+    fn pieces_never_exceed_the_limit() {
+        let text = "lorem ipsum dolor sit amet ".repeat(50);
+        for chunk in chunk_text(&text, 40) {
+            assert!(chunk.len() <= 40, "{:?} is {} bytes", chunk, chunk.len());
+        }
+    }
+
+    #[test]
+    fn splits_unbroken_text_on_char_boundaries() {
+        let chunks = chunk_text("ééééé", 3);
+        assert_eq!(chunks, vec!["é", "é", "é", "é", "é"]);
+
+        // A character wider than the limit still makes progress
+        assert_eq!(chunk_text("🦀🦀", 1), vec!["🦀", "🦀"]);
+    }
+
+    #[test]
+    fn keeps_all_the_words_in_order() {
+        let text = "The quick brown fox. Jumps over\n\nthe lazy dog again and again.";
+        let rejoined = chunk_text(text, 12).join(" ");
+        let words = |s: &str| s.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(words(&rejoined), words(text));
+    }
+}
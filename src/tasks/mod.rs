@@ -0,0 +1,85 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Higher-level tools (`__summarize`, `__translate`, ...) built on top of plain inference.
+
+pub mod budget;
+pub mod chunk;
+pub mod prompt;
+pub mod summarize;
+pub mod translate;
+
+use worker::Env;
+use crate::ai::{AiBridge, AiError, AiResponse, TokenUsage};
+use serde_json::json;
+
+pub use budget::TimeBudget;
+
+/// Built-in tools are reserved under the `__` prefix so they can't collide with model ids.
+pub fn is_builtin(name: &str) -> bool {
+    name.starts_with("__")
+}
+
+/// Run a built-in task by tool name.
+pub async fn run(
+    env: &Env,
+    name: &str,
+    args: serde_json::Value,
+    budget: &TimeBudget,
+) -> Result<AiResponse, AiError> {
+    match name {
+        "__summarize" => summarize::run(env, args, budget).await,
+        "__translate" => translate::run(env, args, budget).await,
+        "__prompt" => prompt::run(env, args, budget).await,
+        _ => Err(AiError::InvalidInput(format!("Unknown tool: {}", name))),
+    }
+}
+
+/// Text generated by a single LLM call, with its cost.
+pub(crate) struct Completion {
+    pub text: String,
+    pub neurons_used: u32,
+    pub estimated_neurons: u32,
+    pub neurons_reported: bool,
+    pub usage: TokenUsage,
+    /// Time spent waiting on the backend, from `_meta.latency_ms`.
+    pub latency_ms: u64,
+}
+
+/// One LLM completion via `AiBridge`, with no retries past the end of `budget`.
+pub(crate) async fn complete(
+    env: &Env,
+    model: &str,
+    prompt: String,
+    max_tokens: u32,
+    budget: &TimeBudget,
+) -> Result<Completion, AiError> {
+    let result = AiBridge::run_inference(
+        env,
+        model,
+        json!({ "prompt": prompt, "max_tokens": max_tokens }),
+        Some(budget.deadline_ms()),
+    )
+    .await?;
+
+    let text = result.result
+        .get("response")
+        .and_then(|r| r.as_str())
+        .ok_or_else(|| AiError::Backend(format!("{} returned no text response", model)))?
+        .trim()
+        .to_string();
+
+    Ok(Completion {
+        text,
+        neurons_used: result.neurons_used,
+        estimated_neurons: result.estimated_neurons,
+        neurons_reported: result.neurons_reported,
+        usage: result.usage.unwrap_or_default(),
+        latency_ms: latency_ms(&result),
+    })
+}
+
+/// The backend latency `AiBridge` recorded for a single inference.
+pub(crate) fn latency_ms(result: &AiResponse) -> u64 {
+    result.meta.get("latency_ms").and_then(|v| v.as_u64()).unwrap_or(0)
+}
@@ -0,0 +1,112 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Operator-defined prompt templates (`PROMPT_MODELS`), each bound to a model.
+//! They're listed as MCP prompts and run with the `__prompt` tool.
+//!
+//! `PROMPT_MODELS` is a JSON object mapping prompt names to
+//! `{ "model": "...", "template": "...", "description": "..." }`; the template
+//! refers to arguments as `{{name}}`, and every placeholder is required.
+
+use std::collections::BTreeMap;
+use worker::{console_warn, Env};
+use crate::ai::{AiBridge, AiError, AiResponse};
+use crate::config;
+use crate::tasks::TimeBudget;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptModel {
+    pub model: String,
+    pub template: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl PromptModel {
+    /// Argument names in the order they first appear in the template.
+    pub fn arguments(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for piece in placeholders(&self.template) {
+            if let Piece::Placeholder(name) = piece {
+                if !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names
+    }
+
+    /// Fill in every `{{name}}` from `args`, failing on the first one missing.
+    pub fn render(&self, args: &serde_json::Map<String, serde_json::Value>) -> Result<String, AiError> {
+        let mut rendered = String::with_capacity(self.template.len());
+        for piece in placeholders(&self.template) {
+            match piece {
+                Piece::Text(text) => rendered.push_str(text),
+                Piece::Placeholder(name) => match args.get(name) {
+                    Some(serde_json::Value::String(s)) => rendered.push_str(s),
+                    Some(other) if !other.is_null() => rendered.push_str(&other.to_string()),
+                    _ => return Err(AiError::InvalidInput(format!("Missing prompt argument '{}'", name))),
+                },
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template into literal text and `{{name}}` placeholders. An unclosed
+/// or empty `{{` is kept as text.
+fn placeholders(template: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + 2 + len].trim();
+        if name.is_empty() {
+            pieces.push(Piece::Text(&rest[..start + 4 + len]));
+        } else {
+            pieces.push(Piece::Text(&rest[..start]));
+            pieces.push(Piece::Placeholder(name));
+        }
+        rest = &rest[start + 4 + len..];
+    }
+    pieces.push(Piece::Text(rest));
+    pieces
+}
+
+/// The configured prompts by name. A malformed `PROMPT_MODELS` is ignored with a warning.
+pub fn configured(env: &Env) -> BTreeMap<String, PromptModel> {
+    let Some(raw) = config::var(env, "PROMPT_MODELS") else {
+        return BTreeMap::new();
+    };
+    serde_json::from_str(&raw).unwrap_or_else(|e| {
+        console_warn!("Ignoring PROMPT_MODELS: {}", e);
+        BTreeMap::new()
+    })
+}
+
+/// `__prompt`: render the named prompt with `arguments` and run it on its model.
+pub async fn run(env: &Env, args: serde_json::Value, budget: &TimeBudget) -> Result<AiResponse, AiError> {
+    let name = args.get("name")
+        .and_then(|n| n.as_str())
+        .ok_or_else(|| AiError::InvalidInput("Missing 'name' field".to_string()))?;
+    let prompt = configured(env)
+        .remove(name)
+        .ok_or_else(|| AiError::InvalidInput(format!("Unknown prompt: {}", name)))?;
+
+    let empty = serde_json::Map::new();
+    let arguments = args.get("arguments").and_then(|a| a.as_object()).unwrap_or(&empty);
+    let rendered = prompt.render(arguments)?;
+
+    let mut response = budget.track(AiBridge::run_inference(env, &prompt.model, json!({ "prompt": rendered }), Some(budget.deadline_ms()))).await?;
+    response.meta.insert("prompt".to_string(), json!(name));
+    Ok(response)
+}
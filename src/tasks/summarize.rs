@@ -0,0 +1,152 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use worker::{console_warn, Env};
+use crate::ai::{AiError, AiResponse, ModelRegistry, TokenUsage};
+use crate::ai::models::ModelCategory;
+use crate::tasks::{chunk, complete, TimeBudget};
+use serde_json::json;
+
+/// Roughly 3k tokens, leaving headroom in an 8k context for the prompt and output.
+const MAX_CHUNK_CHARS: usize = 12_000;
+
+/// Upper bound on reduce rounds so a pathological input can't loop forever.
+const MAX_REDUCE_ROUNDS: usize = 4;
+
+pub async fn run(env: &Env, args: serde_json::Value, budget: &TimeBudget) -> Result<AiResponse, AiError> {
+    let text = args.get("text")
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| AiError::InvalidInput("Missing 'text' field".to_string()))?;
+    let max_length = args.get("max_length")
+        .and_then(|m| m.as_u64())
+        .unwrap_or(150)
+        .clamp(10, 2000) as u32;
+    // `style` was already checked against the tool schema's enum
+    let style_instruction = match args.get("style").and_then(|s| s.as_str()) {
+        Some("bullets") => "as a short list of bullet points",
+        Some("tldr") => "as a one or two sentence TL;DR",
+        _ => "as a single concise paragraph",
+    };
+    let model = match args.get("model").and_then(|m| m.as_str()) {
+        Some(model) => model.to_string(),
+        None => ModelRegistry::default_for(env, &ModelCategory::Llm),
+    };
+
+    let mut neurons_used = 0;
+    let mut estimated_neurons = 0;
+    let mut neurons_reported = true;
+    let mut usage = TokenUsage::default();
+    let mut calls = 0;
+    let mut latency_ms = 0;
+    let mut current = text.to_string();
+    let mut exhausted = false;
+    // Set when a call fails after earlier ones succeeded; what's done is still returned
+    let mut failure = None;
+
+    // Map-reduce: summarize each chunk, then summarize the joined summaries
+    // until everything fits in a single call
+    for _ in 0..MAX_REDUCE_ROUNDS {
+        let chunks = chunk::chunk_text(&current, MAX_CHUNK_CHARS);
+        if chunks.len() <= 1 {
+            break;
+        }
+
+        let total = chunks.len();
+        let mut partials = Vec::with_capacity(total);
+        for piece in chunks {
+            if calls > 0 && !budget.allows_another_call() {
+                exhausted = true;
+                break;
+            }
+            let prompt = format!(
+                "Summarize the key points of the following excerpt in a few sentences.\n\nExcerpt:\n{}\n\nSummary:",
+                piece
+            );
+            let partial = match budget.track(complete(env, &model, prompt, 256, budget)).await {
+                Ok(partial) => partial,
+                Err(e) if calls > 0 => {
+                    console_warn!("Summarizing a section failed, returning a partial summary: {}", e);
+                    failure = Some(e.to_string());
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            neurons_used += partial.neurons_used;
+            estimated_neurons += partial.estimated_neurons;
+            neurons_reported &= partial.neurons_reported;
+            usage.add(&partial.usage);
+            latency_ms += partial.latency_ms;
+            calls += 1;
+            partials.push(partial.text);
+        }
+        let stopped = exhausted || failure.is_some();
+        // Stopping before this round's first section leaves the last round's summaries
+        if !(stopped && partials.is_empty()) {
+            current = partials.join("\n\n");
+        }
+
+        if stopped {
+            // Out of time, or a call failed: hand back the partial summaries rather
+            // than risk being killed or throw away sections already paid for
+            let reason = if exhausted { "Time budget exhausted" } else { "A section failed" };
+            current = format!(
+                "{}\n\n[{} after summarizing {} of {} sections; this summary is partial]",
+                current, reason, partials.len(), total
+            );
+            break;
+        }
+    }
+
+    let summary = if exhausted || failure.is_some() {
+        current
+    } else if calls > 0 && !budget.allows_another_call() {
+        exhausted = true;
+        format!(
+            "{}\n\n[Time budget exhausted before the final pass; these are the per-section summaries]",
+            current
+        )
+    } else {
+        let prompt = format!(
+            "Summarize the following text {} in at most {} words.\n\nText:\n{}\n\nSummary:",
+            style_instruction, max_length, current
+        );
+        match budget.track(complete(env, &model, prompt, max_length * 2, budget)).await {
+            Ok(summary) => {
+                neurons_used += summary.neurons_used;
+                estimated_neurons += summary.estimated_neurons;
+                neurons_reported &= summary.neurons_reported;
+                usage.add(&summary.usage);
+                latency_ms += summary.latency_ms;
+                calls += 1;
+                summary.text
+            }
+            Err(e) if calls > 0 => {
+                console_warn!("The final summary pass failed, returning the section summaries: {}", e);
+                failure = Some(e.to_string());
+                format!("{}\n\n[The final pass failed; these are the per-section summaries]", current)
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    let mut meta = serde_json::Map::new();
+    meta.insert("calls".to_string(), json!(calls));
+    meta.insert("latency_ms".to_string(), json!(latency_ms));
+    if exhausted {
+        meta.insert("budget_exhausted".to_string(), json!(true));
+    }
+    if let Some(error) = failure {
+        meta.insert("failed_call".to_string(), json!(error));
+    }
+
+    Ok(AiResponse {
+        result: json!(summary),
+        neurons_used,
+        estimated_neurons,
+        neurons_reported: neurons_reported && calls > 0,
+        category: ModelRegistry::lookup(env, &model).map(|m| m.category),
+        model_used: model,
+        usage: Some(usage),
+        meta,
+    })
+}
@@ -0,0 +1,168 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+use worker::Env;
+use crate::ai::{AiBridge, AiError, AiResponse, ModelRegistry, TokenUsage};
+use crate::ai::models::ModelCategory;
+use crate::tasks::{self, chunk, complete, Completion, TimeBudget};
+use serde_json::json;
+
+/// Translations come back about as long as their input, so chunks stay small.
+const MAX_CHUNK_CHARS: usize = 4_000;
+
+/// ISO 639-1 codes accepted for `source_lang` / `target_lang`.
+pub const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("bn", "Bengali"),
+    ("bg", "Bulgarian"),
+    ("ca", "Catalan"),
+    ("cs", "Czech"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("et", "Estonian"),
+    ("fa", "Persian"),
+    ("fi", "Finnish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("hr", "Croatian"),
+    ("hu", "Hungarian"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("lt", "Lithuanian"),
+    ("lv", "Latvian"),
+    ("ms", "Malay"),
+    ("nl", "Dutch"),
+    ("no", "Norwegian"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ro", "Romanian"),
+    ("ru", "Russian"),
+    ("sk", "Slovak"),
+    ("sl", "Slovenian"),
+    ("sr", "Serbian"),
+    ("sv", "Swedish"),
+    ("sw", "Swahili"),
+    ("ta", "Tamil"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("ur", "Urdu"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+pub fn language_name(code: &str) -> Option<&'static str> {
+    LANGUAGES.iter().find(|(c, _)| *c == code).map(|(_, name)| *name)
+}
+
+fn parse_language(args: &serde_json::Value, field: &str) -> Result<Option<(String, &'static str)>, AiError> {
+    let Some(code) = args.get(field).and_then(|v| v.as_str()) else {
+        return Ok(None);
+    };
+    let code = code.trim().to_ascii_lowercase();
+    let name = language_name(&code).ok_or_else(|| {
+        AiError::InvalidInput(format!("Unknown {} '{}': expected an ISO 639-1 code such as 'en' or 'fr'", field, code))
+    })?;
+    Ok(Some((code, name)))
+}
+
+pub async fn run(env: &Env, args: serde_json::Value, budget: &TimeBudget) -> Result<AiResponse, AiError> {
+    let text = args.get("text")
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| AiError::InvalidInput("Missing 'text' field".to_string()))?;
+    let (target_code, target_name) = parse_language(&args, "target_lang")?
+        .ok_or_else(|| AiError::InvalidInput("Missing 'target_lang' field".to_string()))?;
+    let source = parse_language(&args, "source_lang")?;
+    let model = match args.get("model").and_then(|m| m.as_str()) {
+        Some(model) => model.to_string(),
+        None => ModelRegistry::default_for(env, &ModelCategory::Llm),
+    };
+
+    let mut neurons_used = 0;
+    let mut estimated_neurons = 0;
+    let mut neurons_reported = true;
+    let mut usage = TokenUsage::default();
+    let mut latency_ms = 0;
+    let mut translated = Vec::new();
+
+    let chunks = chunk::chunk_text(text, MAX_CHUNK_CHARS);
+    let total = chunks.len();
+
+    // Translate chunk by chunk, keeping the original order
+    for piece in chunks {
+        if !translated.is_empty() && !budget.allows_another_call() {
+            break;
+        }
+
+        let done = if model.contains("m2m100") {
+            // Dedicated translation model: takes the language codes directly
+            let source_code = source.as_ref().map_or("en", |(code, _)| code.as_str());
+            let result = budget.track(AiBridge::run_inference(env, &model, json!({
+                "text": piece,
+                "source_lang": source_code,
+                "target_lang": target_code,
+            }), Some(budget.deadline_ms())))
+            .await?;
+            let output = result.result
+                .get("translated_text")
+                .and_then(|t| t.as_str())
+                .ok_or_else(|| AiError::Backend(format!("{} returned no translated_text", model)))?
+                .to_string();
+            Completion {
+                text: output,
+                neurons_used: result.neurons_used,
+                estimated_neurons: result.estimated_neurons,
+                neurons_reported: result.neurons_reported,
+                usage: result.usage.unwrap_or_default(),
+                latency_ms: tasks::latency_ms(&result),
+            }
+        } else {
+            let from = source.as_ref().map_or(String::new(), |(_, name)| format!(" from {}", name));
+            let prompt = format!(
+                "Translate the following text{} to {}. Reply with only the translation.\n\nText:\n{}\n\nTranslation:",
+                from, target_name, piece
+            );
+            let max_tokens = (piece.len() / 2).clamp(256, 2048) as u32;
+            budget.track(complete(env, &model, prompt, max_tokens, budget)).await?
+        };
+
+        neurons_used += done.neurons_used;
+        estimated_neurons += done.estimated_neurons;
+        neurons_reported &= done.neurons_reported;
+        usage.add(&done.usage);
+        latency_ms += done.latency_ms;
+        translated.push(done.text);
+    }
+
+    let mut meta = serde_json::Map::new();
+    meta.insert("target_lang".to_string(), json!(target_code));
+    meta.insert("chunks".to_string(), json!(translated.len()));
+    meta.insert("latency_ms".to_string(), json!(latency_ms));
+
+    let mut output = translated.join("\n\n");
+    if translated.len() < total {
+        // Out of time: return what was translated rather than risk being killed
+        meta.insert("budget_exhausted".to_string(), json!(true));
+        output = format!(
+            "{}\n\n[Time budget exhausted after translating {} of {} sections; this translation is partial]",
+            output, translated.len(), total
+        );
+    }
+
+    Ok(AiResponse {
+        result: json!(output),
+        neurons_used,
+        estimated_neurons,
+        neurons_reported: neurons_reported && !translated.is_empty(),
+        category: ModelRegistry::lookup(env, &model).map(|m| m.category),
+        model_used: model,
+        usage: Some(usage),
+        meta,
+    })
+}
@@ -0,0 +1,152 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Per-client neuron usage for the current day, kept in the optional `STATS`
+//! KV namespace. A daily cron rolls the current window into a dated history
+//! bucket and starts a fresh one.
+//!
+//! Keys:
+//! - `usage:window`: the day (`YYYY-MM-DD`, UTC) the current counters belong to
+//! - `usage:current:<client>`: neurons used so far in that window
+//! - `usage:history:<day>:<client>`: closed-out totals
+//!
+//! Clients are labelled by a hash of their session id (see [`session_label`]),
+//! never the id itself, since the report is readable by any client. Calls
+//! without a session the `SESSIONS` store knows was initialized are counted
+//! under [`ANONYMOUS`].
+//!
+//! KV has no atomic increment, so counters are read, added to and written
+//! back: two calls from one client finishing at the same moment can lose one
+//! of the increments. The figures are for monitoring, not billing.
+
+use worker::*;
+use serde_json::json;
+
+const BINDING: &str = "STATS";
+pub const USAGE_URI: &str = "stats://usage";
+const WINDOW_KEY: &str = "usage:window";
+const CURRENT_PREFIX: &str = "usage:current:";
+const HISTORY_PREFIX: &str = "usage:history:";
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// The client usage is recorded under when there's no initialized session.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// The client label for a session: a 64-bit FNV-1a hash of its id. Stable, so
+/// one session's calls add up, but not an id anyone could send as their own.
+pub fn session_label(session_id: &str) -> String {
+    let hash = session_id.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("session-{:016x}", hash)
+}
+
+/// Add `neurons` to `client`'s current counter. Meant to run under
+/// `ctx.wait_until`; failures are only logged, and concurrent updates may drop
+/// an increment (see the module docs).
+pub async fn record(env: Env, client: String, neurons: u32) {
+    let Ok(kv) = env.kv(BINDING) else {
+        return;
+    };
+
+    let key = format!("{}{}", CURRENT_PREFIX, client);
+    let result: Result<()> = async {
+        let used = kv.get(&key).text().await?.and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        kv.put(&key, (used + neurons as u64).to_string())?.execute().await?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        console_warn!("Failed to record usage for {}: {}", client, e);
+    }
+}
+
+/// Neurons `client` has used in the current window, or `None` without the
+/// `STATS` binding or if the counter can't be read.
+pub async fn current(env: &Env, client: &str) -> Option<u64> {
+    let kv = env.kv(BINDING).ok()?;
+    let used = kv.get(&format!("{}{}", CURRENT_PREFIX, client)).text().await.ok()?;
+    Some(used.and_then(|v| v.parse::<u64>().ok()).unwrap_or(0))
+}
+
+/// Close out the current window if `now_ms` falls on a later day. Safe to run
+/// twice: once the window has moved to today, a second run does nothing, and an
+/// interrupted run resumes with the counters it hadn't moved yet.
+pub async fn roll_over(env: &Env, now_ms: u64) -> Result<()> {
+    let Ok(kv) = env.kv(BINDING) else {
+        return Ok(());
+    };
+
+    let today = day_string(now_ms);
+    let window = kv.get(WINDOW_KEY).text().await?;
+    if window.as_deref() == Some(today.as_str()) {
+        return Ok(());
+    }
+    // Counters recorded before the first rollover belong to "yesterday"
+    let closing = window.unwrap_or_else(|| day_string(now_ms.saturating_sub(DAY_MS)));
+
+    let keys = current_keys(&kv).await?;
+    for key in &keys {
+        let client = key.trim_start_matches(CURRENT_PREFIX);
+        if let Some(used) = kv.get(key).text().await? {
+            // A plain put, so repeating it after a partial run doesn't double-count
+            kv.put(&format!("{}{}:{}", HISTORY_PREFIX, closing, client), used)?.execute().await?;
+        }
+        kv.delete(key).await?;
+    }
+
+    kv.put(WINDOW_KEY, today.clone())?.execute().await?;
+    console_log!("Rolled over usage for {} ({} clients); new window {}", closing, keys.len(), today);
+    Ok(())
+}
+
+/// Current-window totals per client, or `None` without the `STATS` binding.
+pub async fn usage_report(env: &Env) -> Result<Option<serde_json::Value>> {
+    let Ok(kv) = env.kv(BINDING) else {
+        return Ok(None);
+    };
+
+    let window = kv.get(WINDOW_KEY).text().await?;
+    let mut clients = serde_json::Map::new();
+    for key in current_keys(&kv).await? {
+        let used = kv.get(&key).text().await?.and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        clients.insert(key.trim_start_matches(CURRENT_PREFIX).to_string(), json!(used));
+    }
+
+    Ok(Some(json!({ "window": window, "clients": clients })))
+}
+
+/// Every current-window counter key. A list call returns at most 1000 keys,
+/// so this follows the cursor until the listing is complete.
+async fn current_keys(kv: &kv::KvStore) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(CURRENT_PREFIX.to_string());
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+        keys.extend(page.keys.into_iter().map(|key| key.name));
+        cursor = page.cursor;
+        if page.list_complete || cursor.is_none() {
+            return Ok(keys);
+        }
+    }
+}
+
+/// `YYYY-MM-DD` (UTC) for a Unix timestamp in milliseconds.
+fn day_string(ms: u64) -> String {
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = (ms / DAY_MS) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
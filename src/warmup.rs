@@ -0,0 +1,67 @@
+// Copyright (C) 2026 Jade
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Optional model warm-up: the first request an isolate sees kicks off a trivial
+//! inference for each model in `WARMUP_MODELS`, in the background.
+
+use std::cell::Cell;
+use worker::*;
+use crate::ai::{AiBridge, ModelRegistry};
+use crate::ai::models::ModelCategory;
+use crate::config;
+
+thread_local! {
+    static STARTED: Cell<bool> = const { Cell::new(false) };
+    /// Neurons spent on warm-up in this isolate; never attributed to a caller.
+    static NEURONS_USED: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Comma-separated `WARMUP_MODELS`, or empty when unset.
+pub fn models(env: &Env) -> Vec<String> {
+    config::var(env, "WARMUP_MODELS")
+        .map(|v| v.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+pub fn neurons_used() -> u32 {
+    NEURONS_USED.with(|n| n.get())
+}
+
+/// Start warm-up once per isolate; a no-op when `WARMUP_MODELS` is unset.
+pub fn schedule(env: &Env, ctx: &Context) {
+    if STARTED.with(|s| s.replace(true)) || config::read_only(env) {
+        return;
+    }
+
+    let models = models(env);
+    if !models.is_empty() {
+        ctx.wait_until(run(env.clone(), models));
+    }
+}
+
+async fn run(env: Env, models: Vec<String>) {
+    for id in models {
+        let Some(input) = ModelRegistry::lookup(&env, &id).and_then(|m| warmup_input(&m.category)) else {
+            console_warn!("Skipping warm-up of {}: no cheap input for this model", id);
+            continue;
+        };
+
+        match AiBridge::run_inference(&env, &id, input, None).await {
+            Ok(result) => {
+                NEURONS_USED.with(|n| n.set(n.get().saturating_add(result.neurons_used)));
+                console_log!("Warmed up {} ({} neurons)", id, result.neurons_used);
+            }
+            Err(e) => console_warn!("Warm-up of {} failed: {}", id, e),
+        }
+    }
+}
+
+/// The smallest useful input per category. Image, audio, and vision models
+/// have no cheap input, so they aren't warmed.
+fn warmup_input(category: &ModelCategory) -> Option<serde_json::Value> {
+    match category {
+        ModelCategory::Llm => Some(serde_json::json!({ "prompt": "Hi", "max_tokens": 1, "ignore_system": true })),
+        ModelCategory::Embedding => Some(serde_json::json!({ "text": "warm-up" })),
+        _ => None,
+    }
+}